@@ -0,0 +1,444 @@
+use crate::expand::unbound_variable;
+use crate::shell::Shell;
+
+/// How deeply variables whose values are themselves expressions may refer
+/// to one another.
+const MAX_RECURSION: usize = 1024;
+
+/// Operators, longest first so that the tokenizer prefers them.
+const OPERATORS: &[&str] = &[
+    "<<=", ">>=",
+    "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||",
+    "+=", "-=", "*=", "/=", "%=", "&=", "^=", "|=",
+    "+", "-", "*", "/", "%", "<", ">", "=", "!", "~", "&", "^", "|", "?", ":", "(", ")", ",", "[", "]",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token{
+    Number(i64),
+    Name(String),
+    Operator(&'static str)
+}
+
+/// Evaluates an arithmetic expression with 64-bit signed integers, as in
+/// `$((...))`, `((...))` and `let`. Supports the C operators with their
+/// usual precedence, including assignments, `++`/`--`, `?:` and `,`,
+/// numbers in bases such as `0x1f`, `017` or `2#101`, and variables, whose
+/// values are evaluated as expressions in turn (unset or empty means 0).
+///
+/// # Arguments
+/// * `expression` - The expression, already expanded
+/// * `shell` - Shell state variables are read from and assigned to
+///
+/// # Returns
+/// * `Result<i64, String>` - The value, or a message such as `1/0: division by 0`
+pub fn evaluate(expression: &str, shell: &mut Shell)-> Result<i64, String>{
+    evaluate_nested(expression, shell, 0)
+}
+
+fn evaluate_nested(expression: &str, shell: &mut Shell, depth: usize)-> Result<i64, String>{
+    if depth > MAX_RECURSION {
+        return Err(format!("{}: expression recursion level exceeded", expression.trim()));
+    }
+    let tokens = tokenize(expression)?;
+    if tokens.is_empty() {
+        return Ok(0);
+    }
+    let mut parser = Parser { tokens, pos: 0, expression, shell, depth };
+    let value = parser.comma(true)?;
+    if parser.pos < parser.tokens.len() {
+        return Err(parser.error("syntax error in expression"));
+    }
+    Ok(value)
+}
+
+/// Splits an expression into tokens, each with its byte offset so that
+/// errors can quote the rest of the expression.
+fn tokenize(expression: &str)-> Result<Vec<(Token, usize)>, String>{
+    let mut tokens = Vec::new();
+    let mut rest = expression;
+    while let Some(c) = rest.chars().next() {
+        let offset = expression.len() - rest.len();
+        if c.is_whitespace() {
+            rest = &rest[c.len_utf8()..];
+        }else if c.is_ascii_digit() {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '#' || c == '@' || c == '_')).unwrap_or(rest.len());
+            tokens.push((Token::Number(parse_number(&rest[..end], expression)?), offset));
+            rest = &rest[end..];
+        }else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest.find(|c: char| !(c.is_ascii_alphanumeric() || c == '_')).unwrap_or(rest.len());
+            tokens.push((Token::Name(rest[..end].to_string()), offset));
+            rest = &rest[end..];
+        }else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push((Token::Operator(op), offset));
+            rest = &rest[op.len()..];
+        }else{
+            return Err(format!("{}: syntax error: invalid arithmetic operator (error token is \"{}\")", expression.trim(), rest));
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parses an integer constant: decimal, octal with a leading `0`,
+/// hexadecimal with `0x`, or `base#digits` for bases 2 to 64.
+fn parse_number(text: &str, expression: &str)-> Result<i64, String>{
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        match base.parse::<u32>() {
+            Ok(base) if (2..=64).contains(&base) => (base, digits),
+            _ => return Err(format!("{}: invalid arithmetic base (error token is \"{}\")", expression.trim(), text)),
+        }
+    }else if let Some(digits) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, digits)
+    }else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    }else{
+        (10, text)
+    };
+    let invalid = || format!("{}: value too great for base (error token is \"{}\")", expression.trim(), text);
+    if digits.is_empty() {
+        return Err(invalid());
+    }
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            _ => 63,
+        };
+        if digit >= base {
+            return Err(invalid());
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+    Ok(value)
+}
+
+/// A recursive-descent evaluator. Every level takes `evaluate`, which is
+/// false in the branches skipped by `&&`, `||` and `?:`: those are still
+/// parsed but have no side effects and cannot fail at run time.
+struct Parser<'a>{
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    expression: &'a str,
+    shell: &'a mut Shell,
+    depth: usize
+}
+
+impl Parser<'_>{
+    fn peek(&self)-> Option<&Token>{
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn peek_operator(&self)-> Option<&'static str>{
+        match self.peek() {
+            Some(Token::Operator(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn expect(&mut self, op: &str)-> Result<(), String>{
+        if self.peek_operator() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        }else{
+            Err(self.error(&format!("syntax error: `{}' expected", op)))
+        }
+    }
+
+    fn error(&self, message: &str)-> String{
+        self.error_at(self.pos, message)
+    }
+
+    /// An error whose token is the text from the token at `pos` on.
+    fn error_at(&self, pos: usize, message: &str)-> String{
+        let offset = self.tokens.get(pos).map(|(_, offset)| *offset).unwrap_or(self.expression.len());
+        format!("{}: {} (error token is \"{}\")", self.expression.trim(), message, self.expression[offset..].trim_end())
+    }
+
+    fn comma(&mut self, evaluate: bool)-> Result<i64, String>{
+        let mut value = self.assignment(evaluate)?;
+        while self.peek_operator() == Some(",") {
+            self.pos += 1;
+            value = self.assignment(evaluate)?;
+        }
+        Ok(value)
+    }
+
+    fn assignment(&mut self, evaluate: bool)-> Result<i64, String>{
+        let start = self.pos;
+        if let Some(Token::Name(name)) = self.peek().cloned() {
+            self.pos += 1;
+            let index = self.subscript(evaluate)?;
+            let op = self.peek_operator().filter(|op| {
+                matches!(*op, "=" | "+=" | "-=" | "*=" | "/=" | "%=" | "<<=" | ">>=" | "&=" | "^=" | "|=")
+            });
+            if let Some(op) = op {
+                self.pos += 1;
+                let value_start = self.pos;
+                let value = self.assignment(evaluate)?;
+                if !evaluate {
+                    return Ok(0);
+                }
+                let value = match op {
+                    "=" => value,
+                    op => {
+                        let current = self.variable(&name, index)?;
+                        self.apply(&op[..op.len() - 1], current, value, value_start)?
+                    },
+                };
+                self.store(&name, index, value)?;
+                return Ok(value);
+            }
+            self.pos = start;
+        }
+        self.conditional(evaluate)
+    }
+
+    fn conditional(&mut self, evaluate: bool)-> Result<i64, String>{
+        let condition = self.binary(1, evaluate)?;
+        if self.peek_operator() != Some("?") {
+            return Ok(condition);
+        }
+        self.pos += 1;
+        let if_true = self.assignment(evaluate && condition != 0)?;
+        self.expect(":")?;
+        let if_false = self.assignment(evaluate && condition == 0)?;
+        Ok(if condition != 0 { if_true } else { if_false })
+    }
+
+    /// Parses binary operators of at least `min_precedence`, by precedence
+    /// climbing; `**` is the only right-associative one.
+    fn binary(&mut self, min_precedence: u8, evaluate: bool)-> Result<i64, String>{
+        let mut left = self.unary(evaluate)?;
+        while let Some(op) = self.peek_operator() {
+            let Some(precedence) = precedence(op).filter(|p| *p >= min_precedence) else {
+                break;
+            };
+            self.pos += 1;
+            let evaluate_right = match op {
+                "&&" => evaluate && left != 0,
+                "||" => evaluate && left == 0,
+                _ => evaluate,
+            };
+            let next_precedence = if op == "**" { precedence } else { precedence + 1 };
+            let right_start = self.pos;
+            let right = self.binary(next_precedence, evaluate_right)?;
+            left = match op {
+                "&&" => (left != 0 && right != 0) as i64,
+                "||" => (left != 0 || right != 0) as i64,
+                _ if !evaluate => 0,
+                _ => self.apply(op, left, right, right_start)?,
+            };
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self, evaluate: bool)-> Result<i64, String>{
+        match self.peek_operator() {
+            Some(op @ ("!" | "~" | "-" | "+")) => {
+                self.pos += 1;
+                let value = self.unary(evaluate)?;
+                Ok(match op {
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    "-" => value.wrapping_neg(),
+                    _ => value,
+                })
+            },
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                let Some(Token::Name(name)) = self.peek().cloned() else {
+                    return Err(self.error("syntax error: operand expected"));
+                };
+                self.pos += 1;
+                let index = self.subscript(evaluate)?;
+                if !evaluate {
+                    return Ok(0);
+                }
+                let value = self.variable(&name, index)?.wrapping_add(if op == "++" { 1 } else { -1 });
+                self.store(&name, index, value)?;
+                Ok(value)
+            },
+            _ => self.primary(evaluate),
+        }
+    }
+
+    fn primary(&mut self, evaluate: bool)-> Result<i64, String>{
+        match self.peek().cloned() {
+            Some(Token::Number(value)) => {
+                self.pos += 1;
+                Ok(value)
+            },
+            Some(Token::Operator("(")) => {
+                self.pos += 1;
+                let value = self.comma(evaluate)?;
+                self.expect(")")?;
+                Ok(value)
+            },
+            Some(Token::Name(name)) => {
+                self.pos += 1;
+                let index = self.subscript(evaluate)?;
+                if !evaluate {
+                    if matches!(self.peek_operator(), Some("++" | "--")) {
+                        self.pos += 1;
+                    }
+                    return Ok(0);
+                }
+                let value = self.variable(&name, index)?;
+                if let Some(op @ ("++" | "--")) = self.peek_operator() {
+                    self.pos += 1;
+                    self.store(&name, index, value.wrapping_add(if op == "++" { 1 } else { -1 }))?;
+                }
+                Ok(value)
+            },
+            _ => Err(self.error("syntax error: operand expected")),
+        }
+    }
+
+    /// Parses the `[index]` after a variable name, if there is one.
+    fn subscript(&mut self, evaluate: bool)-> Result<Option<i64>, String>{
+        if self.peek_operator() != Some("[") {
+            return Ok(None);
+        }
+        self.pos += 1;
+        let index = self.comma(evaluate)?;
+        self.expect("]")?;
+        Ok(Some(index))
+    }
+
+    /// The value of a variable or array element, evaluated as an expression.
+    fn variable(&mut self, name: &str, index: Option<i64>)-> Result<i64, String>{
+        let text = match index {
+            Some(index) => self.shell.vars.get_element(name, index),
+            None => self.shell.vars.get_scalar(name),
+        };
+        let text = match text {
+            Some(text) => text,
+            None if self.shell.options.nounset => return Err(unbound_variable(name, self.shell)),
+            None => String::new(),
+        };
+        let text = text.trim();
+        if text.is_empty() {
+            return Ok(0);
+        }
+        match text.parse::<i64>() {
+            Ok(value) => Ok(value),
+            Err(_) => evaluate_nested(text, self.shell, self.depth + 1),
+        }
+    }
+
+    fn store(&mut self, name: &str, index: Option<i64>, value: i64)-> Result<(), String>{
+        match index {
+            Some(index) => self.shell.vars.set_element(name, index, value.to_string()),
+            None => self.shell.vars.set(name, value.to_string()),
+        }
+    }
+
+    /// Applies a binary operator; errors point at the right operand, which
+    /// starts at token `right_start`.
+    fn apply(&self, op: &str, left: i64, right: i64, right_start: usize)-> Result<i64, String>{
+        Ok(match op {
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => return Err(self.error_at(right_start, "division by 0")),
+            "/" => left.wrapping_div(right),
+            "%" => left.wrapping_rem(right),
+            "**" if right < 0 => return Err(self.error_at(right_start, "exponent less than 0")),
+            "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "<" => (left < right) as i64,
+            ">" => (left > right) as i64,
+            "<=" => (left <= right) as i64,
+            ">=" => (left >= right) as i64,
+            "==" => (left == right) as i64,
+            "!=" => (left != right) as i64,
+            "&" => left & right,
+            "^" => left ^ right,
+            _ => left | right,
+        })
+    }
+}
+
+fn precedence(op: &str)-> Option<u8>{
+    Some(match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        "**" => 11,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expression: &str)-> Result<i64, String>{
+        evaluate(expression, &mut Shell::new())
+    }
+
+    #[test]
+    fn follows_c_precedence(){
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("-2 ** 2"), Ok(4));
+        assert_eq!(eval("1 << 2 + 1"), Ok(8));
+        assert_eq!(eval("1 | 2 ^ 3 & 4"), Ok(3));
+        assert_eq!(eval("1 < 2 == 1"), Ok(1));
+        assert_eq!(eval("0 || 1 && 0"), Ok(0));
+        assert_eq!(eval("1 ? 2 : 0 ? 3 : 4"), Ok(2));
+        assert_eq!(eval("!0 + ~0"), Ok(0));
+        assert_eq!(eval("7 / 2, 7 % 2"), Ok(1));
+    }
+
+    #[test]
+    fn reads_base_literals(){
+        assert_eq!(eval("0x1f"), Ok(31));
+        assert_eq!(eval("017"), Ok(15));
+        assert_eq!(eval("2#101"), Ok(5));
+        assert_eq!(eval("16#ff"), Ok(255));
+        assert_eq!(eval("36#z"), Ok(35));
+        assert_eq!(eval("64#_"), Ok(63));
+        assert!(eval("08").is_err());
+        assert!(eval("2#102").is_err());
+        assert!(eval("65#1").is_err());
+    }
+
+    #[test]
+    fn rejects_division_by_zero(){
+        assert!(eval("1 / 0").unwrap_err().ends_with("division by 0 (error token is \"0\")"));
+        assert!(eval("5 % (2 - 2)").unwrap_err().contains("division by 0"));
+        assert!(eval("2 ** -1").unwrap_err().contains("exponent less than 0"));
+    }
+
+    #[test]
+    fn assigns_variables(){
+        let mut shell = Shell::new();
+        assert_eq!(evaluate("x = 5, x += 2, x++", &mut shell), Ok(7));
+        assert_eq!(shell.vars.get_scalar("x").as_deref(), Some("8"));
+        assert_eq!(evaluate("--x * 2", &mut shell), Ok(14));
+        let _ = shell.vars.set("y", "x + 1".to_string());
+        assert_eq!(evaluate("y * 2", &mut shell), Ok(16));
+        assert_eq!(evaluate("unset_in_test + 1", &mut shell), Ok(1));
+    }
+
+    #[test]
+    fn reports_syntax_errors(){
+        assert!(eval("1 +").is_err());
+        assert!(eval("(1").is_err());
+        assert!(eval("1 2").is_err());
+    }
+}
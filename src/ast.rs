@@ -0,0 +1,245 @@
+/// A complete command line: and-or lists run one after another, separated
+/// by `;` or newlines.
+#[derive(Debug, Clone)]
+pub struct Program{
+    pub lists: Vec<AndOrList>
+}
+
+/// Pipelines joined by `&&` and `||`; each operator decides from the status
+/// so far whether the pipeline after it runs.
+#[derive(Debug, Clone)]
+pub struct AndOrList{
+    pub first: Pipeline,
+    pub rest: Vec<(AndOrOp, Pipeline)>,
+    /// Ended by `&`: the list runs as a background job.
+    pub background: bool
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AndOrOp{
+    /// `&&`: run when the status so far is zero.
+    And,
+    /// `||`: run when the status so far is non-zero.
+    Or
+}
+
+/// Commands whose standard output and input are connected by `|`. A leading
+/// `!` inverts the pipeline's status.
+#[derive(Debug, Clone)]
+pub struct Pipeline{
+    pub negated: bool,
+    pub commands: Vec<Command>,
+    /// The source text of the pipeline, which is how job listings show it.
+    pub text: String
+}
+
+/// A single stage of a pipeline.
+#[derive(Debug, Clone)]
+pub enum Command{
+    Simple(SimpleCommand),
+    /// A compound command and the redirections that apply to all of it.
+    Compound(CompoundCommand, Vec<Redirect>),
+    /// `name() body` or `function name body`: defines a function whose body
+    /// is a compound command, rather than running anything.
+    Function(String, Box<Command>)
+}
+
+#[derive(Debug, Clone)]
+pub enum CompoundCommand{
+    /// `((expression))`: succeeds when the expression is non-zero.
+    Arithmetic(Word),
+    /// `{ list; }`: runs the list in the current shell.
+    BraceGroup(Program),
+    /// `( list )`: runs the list in a forked copy of the shell, so changes
+    /// to variables or the directory do not outlive it.
+    Subshell(Program),
+    /// `if` and its `elif` branches as (condition, body) pairs, then the
+    /// `else` body if any.
+    If { branches: Vec<(Program, Program)>, else_body: Option<Program> },
+    /// `while` or, when `until`, `until`: the body runs as long as the
+    /// condition succeeds, or fails for `until`.
+    While { until: bool, condition: Program, body: Program },
+    /// `for name in words`; `for name` alone stands for `for name in "$@"`.
+    For { name: String, words: Vec<Word>, body: Program },
+    /// `case word in pattern|pattern) list;; ... esac`.
+    Case { word: Word, items: Vec<CaseItem> },
+    /// `for ((init; condition; step))`, with an empty condition counting
+    /// as true.
+    ArithmeticFor { init: Word, condition: Word, step: Word, body: Program },
+    /// `[[ expression ]]`: succeeds when the expression is true.
+    Conditional(ConditionalExpr)
+}
+
+/// An expression inside `[[ ... ]]`. Its words are neither split nor
+/// globbed.
+#[derive(Debug, Clone)]
+pub enum ConditionalExpr{
+    /// A word alone: true when it is not empty.
+    Word(Word),
+    /// A unary test such as `-f file`, `-z string` or `-v name`.
+    Unary(String, Word),
+    /// A binary test such as `a == pattern`, `a -lt b` or `a =~ regex`.
+    Binary(Word, String, Word),
+    /// `! expression`.
+    Not(Box<ConditionalExpr>),
+    /// `expression && expression`.
+    And(Box<ConditionalExpr>, Box<ConditionalExpr>),
+    /// `expression || expression`.
+    Or(Box<ConditionalExpr>, Box<ConditionalExpr>)
+}
+
+/// One `pattern|pattern) list` item of a `case` command.
+#[derive(Debug, Clone)]
+pub struct CaseItem{
+    pub patterns: Vec<Word>,
+    pub body: Program,
+    pub terminator: CaseTerminator
+}
+
+/// What happens after the body of a matching `case` item has run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CaseTerminator{
+    /// `;;`: the `case` command is done.
+    Break,
+    /// `;&`: the next body runs as well, whatever its patterns.
+    FallThrough,
+    /// `;;&`: the patterns of the following items are tested too.
+    Continue
+}
+
+/// `NAME=value ... word ... redirect ...` in any order the grammar allows.
+#[derive(Debug, Clone)]
+pub struct SimpleCommand{
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>
+}
+
+/// A `NAME=value` prefix of a simple command.
+#[derive(Debug, Clone)]
+pub struct Assignment{
+    pub name: String,
+    pub value: Word
+}
+
+/// A redirection of the file descriptor `fd` to or from `target`. For
+/// here-documents the target is the body rather than a file name.
+#[derive(Debug, Clone)]
+pub struct Redirect{
+    pub fd: i32,
+    pub op: RedirectOp,
+    pub target: Word
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectOp{
+    /// `<`: read from the target.
+    Input,
+    /// `<<` and `<<-`: read the here-document body.
+    HereDoc,
+    /// `<<<`: read the target word followed by a newline.
+    HereString,
+    /// `>`: create or truncate the target, unless `set -C` protects it.
+    Output,
+    /// `>|`: create or truncate the target even with `set -C`.
+    Clobber,
+    /// `>>`: create or append to the target.
+    Append,
+    /// `<>`: open the target for reading and writing.
+    ReadWrite,
+    /// `<&`: duplicate the descriptor named by the target, or close with `-`.
+    DupInput,
+    /// `>&`: like `DupInput`; a target that is not a number means `&>`.
+    DupOutput,
+    /// `&>`: send stdout and stderr to the target, truncating it.
+    OutputAndError,
+    /// `&>>`: append stdout and stderr to the target.
+    AppendAndError
+}
+
+/// A word as written in the source, split into parts that differ in how
+/// they are quoted and expanded.
+#[derive(Debug, Clone, Default)]
+pub struct Word{
+    pub parts: Vec<WordPart>
+}
+
+#[derive(Debug, Clone)]
+pub enum WordPart{
+    /// Unquoted text.
+    Literal(String),
+    /// A backslash-escaped character outside of quotes.
+    Escaped(char),
+    /// Text between single quotes.
+    SingleQuoted(String),
+    /// Parts between double quotes; only literals and expansions occur here.
+    DoubleQuoted(Vec<WordPart>),
+    /// `$name` or `${...}`.
+    Parameter(ParamExpansion),
+    /// `$(...)` or `` `...` ``: replaced by the output of the commands.
+    CommandSubst(Program),
+    /// `$((...))`: replaced by the value of the expression.
+    Arithmetic(Word)
+}
+
+/// A parameter expansion: the parameter and what to do with its value.
+#[derive(Debug, Clone)]
+pub struct ParamExpansion{
+    /// A variable name or a special parameter such as `?` or `1`.
+    pub name: String,
+    /// The subscript of an array element such as `PIPESTATUS[1]`; `@` and
+    /// `*` select every element.
+    pub index: Option<Word>,
+    pub op: ParamOp
+}
+
+#[derive(Debug, Clone)]
+pub enum ParamOp{
+    /// `$name` and `${name}`.
+    Value,
+    /// `${#name}`.
+    Length,
+    /// `${name-word}`, or `${name:-word}` when `colon` (null counts as unset).
+    Default { colon: bool, word: Word },
+    /// `${name=word}` and `${name:=word}`.
+    Assign { colon: bool, word: Word },
+    /// `${name?word}` and `${name:?word}`.
+    Error { colon: bool, word: Word },
+    /// `${name+word}` and `${name:+word}`.
+    Alternative { colon: bool, word: Word },
+    /// `${name#pattern}`, or `${name##pattern}` when `longest`.
+    RemovePrefix { longest: bool, pattern: Word },
+    /// `${name%pattern}`, or `${name%%pattern}` when `longest`.
+    RemoveSuffix { longest: bool, pattern: Word },
+    /// `${name/pattern/replacement}` and its `//`, `/#` and `/%` forms.
+    Replace { mode: ReplaceMode, pattern: Word, replacement: Word },
+    /// `${name:offset}` and `${name:offset:length}`, both arithmetic.
+    Substring { offset: Word, length: Option<Word> }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaceMode{
+    /// `/`: the first match.
+    First,
+    /// `//`: every match.
+    All,
+    /// `/#`: a match at the start.
+    Prefix,
+    /// `/%`: a match at the end.
+    Suffix
+}
+
+impl Word{
+    /// Returns the word's text when it consists only of unquoted literal
+    /// text, which is what reserved words and assignment names require.
+    pub fn as_literal(&self)-> Option<String>{
+        let mut text = String::new();
+        for part in &self.parts {
+            match part {
+                WordPart::Literal(s) => text.push_str(s),
+                _ => return None,
+            }
+        }
+        Some(text)
+    }
+}
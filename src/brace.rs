@@ -0,0 +1,210 @@
+use crate::ast::{Word, WordPart};
+
+/// A piece of a word during brace expansion: an unquoted character, which
+/// may be part of the brace syntax, or any other part, which never is.
+#[derive(Debug, Clone)]
+enum Item{
+    Char(char),
+    Part(WordPart)
+}
+
+/// Expands the braces of a word into the words they stand for, as the
+/// first expansion. `{a,b}` lists alternatives and `{x..y}` or `{x..y..step}`
+/// a sequence of integers (zero-padded when an end is) or characters. Only
+/// unquoted braces count, and braces that are neither are left alone.
+///
+/// # Arguments
+/// * `word` - The word as parsed
+///
+/// # Returns
+/// * `Vec<Word>` - The resulting words, in order; just `word` without braces
+pub fn expand_braces(word: &Word)-> Vec<Word>{
+    let mut items = Vec::new();
+    for part in &word.parts {
+        match part {
+            WordPart::Literal(text) => items.extend(text.chars().map(Item::Char)),
+            part => items.push(Item::Part(part.clone())),
+        }
+    }
+    expand_items(&items).into_iter().map(|items| to_word(&items)).collect()
+}
+
+fn expand_items(items: &[Item])-> Vec<Vec<Item>>{
+    for open in 0..items.len() {
+        if !is_char(&items[open], '{') {
+            continue;
+        }
+        let Some((close, commas)) = find_close(items, open) else {
+            continue;
+        };
+        let prefix = &items[..open];
+        let suffix = &items[close + 1..];
+        let alternatives: Vec<Vec<Item>> = if commas.is_empty() {
+            match sequence(&items[open + 1..close]) {
+                Some(values) => values.into_iter().map(|value| value.chars().map(Item::Char).collect()).collect(),
+                None => continue,
+            }
+        }else{
+            let mut bounds = vec![open];
+            bounds.extend(&commas);
+            bounds.push(close);
+            bounds.windows(2).map(|pair| items[pair[0] + 1..pair[1]].to_vec()).collect()
+        };
+        let mut results = Vec::new();
+        for alternative in alternatives {
+            let mut combined = prefix.to_vec();
+            combined.extend(alternative);
+            combined.extend_from_slice(suffix);
+            results.extend(expand_items(&combined));
+        }
+        return results;
+    }
+    vec![items.to_vec()]
+}
+
+/// Finds the `}` matching the `{` at `open` and the commas directly inside it.
+fn find_close(items: &[Item], open: usize)-> Option<(usize, Vec<usize>)>{
+    let mut depth = 0;
+    let mut commas = Vec::new();
+    for (i, item) in items.iter().enumerate().skip(open + 1) {
+        if is_char(item, '{') {
+            depth += 1;
+        }else if is_char(item, '}') {
+            if depth == 0 {
+                return Some((i, commas));
+            }
+            depth -= 1;
+        }else if is_char(item, ',') && depth == 0 {
+            commas.push(i);
+        }
+    }
+    None
+}
+
+/// Generates the values of a `x..y[..step]` sequence, or `None` if the text
+/// between the braces is not one.
+fn sequence(items: &[Item])-> Option<Vec<String>>{
+    let mut text = String::new();
+    for item in items {
+        match item {
+            Item::Char(c) => text.push(*c),
+            Item::Part(_) => return None,
+        }
+    }
+    let fields: Vec<&str> = text.split("..").collect();
+    if fields.len() != 2 && fields.len() != 3 {
+        return None;
+    }
+    let step = match fields.get(2) {
+        Some(step) => step.parse::<i64>().ok()?.unsigned_abs().max(1),
+        None => 1,
+    };
+    let (start, end) = (fields[0], fields[1]);
+    if let (Ok(first), Ok(last)) = (start.parse::<i64>(), end.parse::<i64>()) {
+        let padded = |s: &str| s.trim_start_matches('-').len() > 1 && s.trim_start_matches('-').starts_with('0');
+        let width = if padded(start) || padded(end) { start.len().max(end.len()) } else { 0 };
+        return Some(steps(first, last, step).map(|n| {
+            if n < 0 {
+                format!("-{:0>width$}", -n, width = width.saturating_sub(1))
+            }else{
+                format!("{:0>width$}", n, width = width)
+            }
+        }).collect());
+    }
+    let mut start_chars = start.chars();
+    let mut end_chars = end.chars();
+    match (start_chars.next(), start_chars.next(), end_chars.next(), end_chars.next()) {
+        (Some(first), None, Some(last), None) if first.is_ascii_alphabetic() && last.is_ascii_alphabetic() => {
+            Some(steps(first as i64, last as i64, step).map(|n| (n as u8 as char).to_string()).collect())
+        },
+        _ => None,
+    }
+}
+
+/// The numbers from `first` to `last` inclusive, counting up or down.
+fn steps(first: i64, last: i64, step: u64)-> impl Iterator<Item = i64>{
+    let count = first.abs_diff(last) / step + 1;
+    let step = if last < first { -(step as i64) } else { step as i64 };
+    (0..count as i64).map(move |i| first + i * step)
+}
+
+fn is_char(item: &Item, expected: char)-> bool{
+    matches!(item, Item::Char(c) if *c == expected)
+}
+
+fn to_word(items: &[Item])-> Word{
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    for item in items {
+        match item {
+            Item::Char(c) => literal.push(*c),
+            Item::Part(part) => {
+                if !literal.is_empty() {
+                    parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(part.clone());
+            },
+        }
+    }
+    if !literal.is_empty() {
+        parts.push(WordPart::Literal(literal));
+    }
+    Word { parts }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expand(text: &str)-> Vec<String>{
+        let word = Word { parts: vec![WordPart::Literal(text.to_string())] };
+        expand_braces(&word).iter().map(|word| word.as_literal().unwrap()).collect()
+    }
+
+    #[test]
+    fn expands_lists(){
+        assert_eq!(expand("a{b,c}d"), ["abd", "acd"]);
+        assert_eq!(expand("x{a,{b,c}}y"), ["xay", "xby", "xcy"]);
+        assert_eq!(expand("{1..3}{a,b}"), ["1a", "1b", "2a", "2b", "3a", "3b"]);
+        assert_eq!(expand("{a,}"), ["a", ""]);
+        assert_eq!(expand("{,}"), ["", ""]);
+    }
+
+    #[test]
+    fn leaves_other_braces_alone(){
+        assert_eq!(expand("{a}"), ["{a}"]);
+        assert_eq!(expand("a{b,c"), ["a{b,c"]);
+        assert_eq!(expand("{1..a}"), ["{1..a}"]);
+        assert_eq!(expand("{}"), ["{}"]);
+    }
+
+    #[test]
+    fn expands_ranges(){
+        assert_eq!(expand("{1..4}"), ["1", "2", "3", "4"]);
+        assert_eq!(expand("{3..1}"), ["3", "2", "1"]);
+        assert_eq!(expand("{-2..2}"), ["-2", "-1", "0", "1", "2"]);
+        assert_eq!(expand("{a..e..2}"), ["a", "c", "e"]);
+        assert_eq!(expand("{z..w}"), ["z", "y", "x", "w"]);
+    }
+
+    #[test]
+    fn zero_pads_ranges(){
+        assert_eq!(expand("{01..03}"), ["01", "02", "03"]);
+        assert_eq!(expand("{007..9}"), ["007", "008", "009"]);
+        assert_eq!(expand("{-05..5..5}"), ["-05", "000", "005"]);
+    }
+
+    #[test]
+    fn steps_through_ranges(){
+        assert_eq!(expand("{1..10..3}"), ["1", "4", "7", "10"]);
+        assert_eq!(expand("{10..1..4}"), ["10", "6", "2"]);
+        assert_eq!(expand("{1..10..-3}"), ["1", "4", "7", "10"]);
+        assert_eq!(expand("{01..10..3}"), ["01", "04", "07", "10"]);
+    }
+
+    #[test]
+    fn ignores_quoted_braces(){
+        let word = Word { parts: vec![WordPart::Literal("a".to_string()), WordPart::SingleQuoted("{b,c}".to_string())] };
+        assert_eq!(expand_braces(&word).len(), 1);
+    }
+}
@@ -0,0 +1,435 @@
+use std::ffi::CString;
+use std::fs::{self, Metadata};
+use std::os::unix::fs::{FileTypeExt, MetadataExt};
+
+use crate::arith::evaluate;
+use crate::ast::ConditionalExpr;
+use crate::expand::{expand_pattern, expand_regex, expand_word};
+use crate::glob::pattern_matches;
+use crate::parser::is_name;
+use crate::shell::Shell;
+
+/// Operators that test a single operand, in `test`, `[` and `[[ ]]`.
+pub const UNARY_OPERATORS: [&str; 25] = ["-a", "-b", "-c", "-d", "-e", "-f", "-g", "-h", "-k", "-n", "-o", "-p", "-r", "-s",
+    "-t", "-u", "-v", "-w", "-x", "-z", "-G", "-L", "-N", "-O", "-S"];
+
+/// Operators that compare two operands. `[[ ]]` also has `=~`, and `test`
+/// has `-a` and `-o` to combine tests.
+pub const BINARY_OPERATORS: [&str; 14] = ["=", "==", "!=", "<", ">", "-eq", "-ne", "-lt", "-le", "-gt", "-ge", "-nt", "-ot", "-ef"];
+
+/// Runs the `test` and `[` built-ins. Up to four arguments are read by the
+/// POSIX rules, which look at the number of arguments first; longer
+/// expressions are parsed with `!`, `-a`, `-o` and parentheses, `-a`
+/// binding tighter than `-o`.
+///
+/// # Arguments
+/// * `argv` - `test` or `[` and its arguments, the last of which must be `]` for `[`
+/// * `shell` - Shell state, for `-v` and `-o`
+///
+/// # Returns
+/// * `i32` - 0 if the expression is true, 1 if it is false, 2 on a usage error
+pub fn run_test(argv: &[String], shell: &mut Shell)-> i32{
+    let name = argv[0].as_str();
+    let mut args = &argv[1..];
+    if name == "[" {
+        match args.split_last() {
+            Some((last, rest)) if last == "]" => args = rest,
+            _ => {
+                eprintln!("[: missing `]'");
+                return 2;
+            }
+        }
+    }
+    match test_arguments(args, shell) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            eprintln!("{}: {}", name, e);
+            2
+        }
+    }
+}
+
+fn test_arguments(args: &[String], shell: &mut Shell)-> Result<bool, String>{
+    let arg = |i: usize| args[i].as_str();
+    match args.len() {
+        0 => Ok(false),
+        1 => Ok(!args[0].is_empty()),
+        2 if arg(0) == "!" => Ok(args[1].is_empty()),
+        2 if UNARY_OPERATORS.contains(&arg(0)) => unary_test(arg(0), arg(1), shell),
+        2 => Err(format!("{}: unary operator expected", arg(0))),
+        3 if arg(1) == "-a" => Ok(!args[0].is_empty() && !args[2].is_empty()),
+        3 if arg(1) == "-o" => Ok(!args[0].is_empty() || !args[2].is_empty()),
+        3 if BINARY_OPERATORS.contains(&arg(1)) => binary_test(arg(0), arg(1), arg(2), false, shell),
+        3 if arg(0) == "!" => Ok(!test_arguments(&args[1..], shell)?),
+        3 if arg(0) == "(" && arg(2) == ")" => Ok(!args[1].is_empty()),
+        3 => Err(format!("{}: binary operator expected", arg(1))),
+        4 if arg(0) == "!" => Ok(!test_arguments(&args[1..], shell)?),
+        4 if arg(0) == "(" && arg(3) == ")" => test_arguments(&args[1..3], shell),
+        _ => {
+            let mut parser = TestParser { args, pos: 0, shell };
+            let result = parser.parse_or()?;
+            if parser.pos < args.len() {
+                return Err("too many arguments".to_string());
+            }
+            Ok(result)
+        },
+    }
+}
+
+/// Evaluates the arguments of `test` while it reads them, for expressions
+/// too long for the POSIX rules.
+struct TestParser<'a>{
+    args: &'a [String],
+    pos: usize,
+    shell: &'a mut Shell
+}
+
+impl TestParser<'_>{
+    fn peek(&self, offset: usize)-> Option<&str>{
+        self.args.get(self.pos + offset).map(String::as_str)
+    }
+
+    fn parse_or(&mut self)-> Result<bool, String>{
+        let mut result = self.parse_and()?;
+        while self.peek(0) == Some("-o") {
+            self.pos += 1;
+            result = self.parse_and()? || result;
+        }
+        Ok(result)
+    }
+
+    fn parse_and(&mut self)-> Result<bool, String>{
+        let mut result = self.parse_term()?;
+        while self.peek(0) == Some("-a") {
+            self.pos += 1;
+            result = self.parse_term()? && result;
+        }
+        Ok(result)
+    }
+
+    fn parse_term(&mut self)-> Result<bool, String>{
+        let args = self.args;
+        let Some(arg) = args.get(self.pos).map(String::as_str) else {
+            return Err("argument expected".to_string());
+        };
+        if self.peek(2).is_some() && self.peek(1).is_some_and(|op| BINARY_OPERATORS.contains(&op)) {
+            self.pos += 3;
+            return binary_test(&args[self.pos - 3], &args[self.pos - 2], &args[self.pos - 1], false, self.shell);
+        }
+        match arg {
+            "!" => {
+                self.pos += 1;
+                Ok(!self.parse_term()?)
+            },
+            "(" => {
+                self.pos += 1;
+                let result = self.parse_or()?;
+                if self.peek(0) != Some(")") {
+                    return Err("`)' expected".to_string());
+                }
+                self.pos += 1;
+                Ok(result)
+            },
+            _ if UNARY_OPERATORS.contains(&arg) && self.peek(1).is_some() => {
+                self.pos += 2;
+                unary_test(&args[self.pos - 2], &args[self.pos - 1], self.shell)
+            },
+            _ => {
+                self.pos += 1;
+                Ok(!arg.is_empty())
+            },
+        }
+    }
+}
+
+/// Runs a `[[ ]]` command. Its words are expanded without splitting or
+/// globbing; the right of `==`, `=` and `!=` is a pattern, the right of
+/// `=~` an extended regular expression whose match and groups are put in
+/// `BASH_REMATCH`, and integer comparisons evaluate arithmetic.
+///
+/// # Arguments
+/// * `expression` - The expression between `[[` and `]]`
+/// * `shell` - Shell state
+///
+/// # Returns
+/// * `i32` - 0 if the expression is true, 1 if it is false, 2 on an error
+pub fn run_conditional(expression: &ConditionalExpr, shell: &mut Shell)-> i32{
+    match evaluate_conditional(expression, shell) {
+        Ok(true) => 0,
+        Ok(false) => 1,
+        Err(e) => {
+            if !e.is_empty() {
+                eprintln!("{}", e);
+            }
+            2
+        }
+    }
+}
+
+/// Evaluates a `[[ ]]` expression. An empty error is one that is not
+/// reported, such as an invalid regular expression.
+fn evaluate_conditional(expression: &ConditionalExpr, shell: &mut Shell)-> Result<bool, String>{
+    match expression {
+        ConditionalExpr::Word(word) => Ok(!expand_word(word, shell)?.is_empty()),
+        ConditionalExpr::Unary(op, word) => {
+            let operand = expand_word(word, shell)?;
+            unary_test(op, &operand, shell)
+        },
+        ConditionalExpr::Binary(left, op, right) => {
+            let left = expand_word(left, shell)?;
+            match op.as_str() {
+                "=" | "==" | "!=" => {
+                    let pattern = expand_pattern(right, shell)?;
+                    Ok(pattern_matches(&pattern, &left, true) == (op != "!="))
+                },
+                "=~" => {
+                    let regex = expand_regex(right, shell)?;
+                    regex_matches(&regex, &left, shell)
+                },
+                _ => {
+                    let right = expand_word(right, shell)?;
+                    binary_test(&left, op, &right, true, shell)
+                },
+            }
+        },
+        ConditionalExpr::Not(expression) => Ok(!evaluate_conditional(expression, shell)?),
+        ConditionalExpr::And(left, right) => Ok(evaluate_conditional(left, shell)? && evaluate_conditional(right, shell)?),
+        ConditionalExpr::Or(left, right) => Ok(evaluate_conditional(left, shell)? || evaluate_conditional(right, shell)?),
+    }
+}
+
+/// Matches a string against an extended regular expression, setting
+/// `BASH_REMATCH` to the match and its groups, or to nothing when it does
+/// not match.
+fn regex_matches(regex: &str, text: &str, shell: &mut Shell)-> Result<bool, String>{
+    let (Ok(regex), Ok(text_c)) = (CString::new(regex), CString::new(text)) else {
+        return Err(String::new());
+    };
+    let mut groups = Vec::new();
+    let matched = unsafe {
+        let mut compiled: libc::regex_t = std::mem::zeroed();
+        if libc::regcomp(&mut compiled, regex.as_ptr(), libc::REG_EXTENDED) != 0 {
+            return Err(String::new());
+        }
+        let mut matches = vec![libc::regmatch_t { rm_so: -1, rm_eo: -1 }; count_groups(regex.as_bytes()) + 1];
+        let matched = libc::regexec(&compiled, text_c.as_ptr(), matches.len(), matches.as_mut_ptr(), 0) == 0;
+        libc::regfree(&mut compiled);
+        if matched {
+            groups = matches.iter()
+                .map(|m| if m.rm_so < 0 { String::new() } else { String::from_utf8_lossy(&text.as_bytes()[m.rm_so as usize..m.rm_eo as usize]).into_owned() })
+                .collect();
+        }
+        matched
+    };
+    shell.vars.set_array("BASH_REMATCH", groups)?;
+    Ok(matched)
+}
+
+/// Counts the groups of an extended regular expression: its `(`s outside
+/// bracket expressions and not escaped.
+fn count_groups(regex: &[u8])-> usize{
+    let mut count = 0;
+    let mut i = 0;
+    while i < regex.len() {
+        match regex[i] {
+            b'\\' => i += 1,
+            b'(' => count += 1,
+            b'[' => {
+                i += 1;
+                if regex.get(i) == Some(&b'^') {
+                    i += 1;
+                }
+                if regex.get(i) == Some(&b']') {
+                    i += 1;
+                }
+                while i < regex.len() && regex[i] != b']' {
+                    if regex[i] == b'[' && matches!(regex.get(i + 1), Some(b':' | b'.' | b'=')) {
+                        let close = regex[i + 1];
+                        i += 2;
+                        while i + 1 < regex.len() && !(regex[i] == close && regex[i + 1] == b']') {
+                            i += 1;
+                        }
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            },
+            _ => {},
+        }
+        i += 1;
+    }
+    count
+}
+
+/// Applies a unary operator of `test` or `[[ ]]` to its operand.
+fn unary_test(op: &str, operand: &str, shell: &mut Shell)-> Result<bool, String>{
+    let metadata = || fs::metadata(operand).ok();
+    let mode = |bits: u32| metadata().is_some_and(|m| m.mode() & bits != 0);
+    Ok(match op {
+        "-z" => operand.is_empty(),
+        "-n" => !operand.is_empty(),
+        "-v" => is_variable_set(operand, shell),
+        "-o" => shell.options.option_mut(operand).is_some_and(|on| *on),
+        "-t" => operand.trim().parse::<i32>().is_ok_and(|fd| unsafe { libc::isatty(fd) } == 1),
+        "-e" | "-a" => metadata().is_some(),
+        "-f" => metadata().is_some_and(|m| m.is_file()),
+        "-d" => metadata().is_some_and(|m| m.is_dir()),
+        "-b" => metadata().is_some_and(|m| m.file_type().is_block_device()),
+        "-c" => metadata().is_some_and(|m| m.file_type().is_char_device()),
+        "-p" => metadata().is_some_and(|m| m.file_type().is_fifo()),
+        "-S" => metadata().is_some_and(|m| m.file_type().is_socket()),
+        "-h" | "-L" => fs::symlink_metadata(operand).is_ok_and(|m| m.file_type().is_symlink()),
+        "-s" => metadata().is_some_and(|m| m.len() > 0),
+        "-u" => mode(libc::S_ISUID),
+        "-g" => mode(libc::S_ISGID),
+        "-k" => mode(libc::S_ISVTX),
+        "-r" => accessible(operand, libc::R_OK),
+        "-w" => accessible(operand, libc::W_OK),
+        "-x" => accessible(operand, libc::X_OK),
+        "-O" => metadata().is_some_and(|m| m.uid() == unsafe { libc::geteuid() }),
+        "-G" => metadata().is_some_and(|m| m.gid() == unsafe { libc::getegid() }),
+        "-N" => metadata().is_some_and(|m| modified(&m) > (m.atime(), m.atime_nsec())),
+        _ => return Err(format!("{}: unary operator expected", op)),
+    })
+}
+
+/// Applies a binary operator of `test` or `[[ ]]`. With `arithmetic` the
+/// operands of integer comparisons are arithmetic expressions, as in
+/// `[[ ]]`; otherwise they must be integers.
+fn binary_test(left: &str, op: &str, right: &str, arithmetic: bool, shell: &mut Shell)-> Result<bool, String>{
+    let mut integers = || -> Result<(i64, i64), String>{
+        if arithmetic {
+            Ok((evaluate(left, shell)?, evaluate(right, shell)?))
+        }else{
+            Ok((integer(left)?, integer(right)?))
+        }
+    };
+    Ok(match op {
+        "=" | "==" => left == right,
+        "!=" => left != right,
+        "<" => left < right,
+        ">" => left > right,
+        "-eq" => integers().map(|(a, b)| a == b)?,
+        "-ne" => integers().map(|(a, b)| a != b)?,
+        "-lt" => integers().map(|(a, b)| a < b)?,
+        "-le" => integers().map(|(a, b)| a <= b)?,
+        "-gt" => integers().map(|(a, b)| a > b)?,
+        "-ge" => integers().map(|(a, b)| a >= b)?,
+        "-nt" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(a), Ok(b)) => modified(&a) > modified(&b),
+            (a, b) => a.is_ok() && b.is_err(),
+        },
+        "-ot" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(a), Ok(b)) => modified(&a) < modified(&b),
+            (a, b) => a.is_err() && b.is_ok(),
+        },
+        "-ef" => match (fs::metadata(left), fs::metadata(right)) {
+            (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+            _ => false,
+        },
+        _ => return Err(format!("{}: binary operator expected", op)),
+    })
+}
+
+/// Parses an operand of an integer comparison in `test`.
+fn integer(text: &str)-> Result<i64, String>{
+    text.trim().parse().map_err(|_| format!("{}: integer expression expected", text))
+}
+
+fn modified(metadata: &Metadata)-> (i64, i64){
+    (metadata.mtime(), metadata.mtime_nsec())
+}
+
+/// Whether the shell's effective user may access a file in a given way.
+fn accessible(path: &str, mode: i32)-> bool{
+    match CString::new(path) {
+        Ok(path) => unsafe { libc::faccessat(libc::AT_FDCWD, path.as_ptr(), mode, libc::AT_EACCESS) == 0 },
+        Err(_) => false,
+    }
+}
+
+/// Whether `-v` finds a variable set: `name`, or `name[index]` for an
+/// element of an array.
+fn is_variable_set(operand: &str, shell: &mut Shell)-> bool{
+    let Some((name, index)) = operand.strip_suffix(']').and_then(|rest| rest.split_once('[')) else {
+        return is_name(operand) && shell.vars.get_scalar(operand).is_some();
+    };
+    if !is_name(name) {
+        return false;
+    }
+    match index {
+        "@" | "*" => shell.vars.get(name).is_some_and(|variable| variable.value.is_some()),
+        _ => match evaluate(index, shell) {
+            Ok(index) => shell.vars.get_element(name, index).is_some(),
+            Err(_) => false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test(args: &[&str])-> i32{
+        let argv: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        run_test(&argv, &mut Shell::new())
+    }
+
+    #[test]
+    fn follows_the_posix_argument_rules(){
+        assert_eq!(test(&["test"]), 1);
+        assert_eq!(test(&["test", "x"]), 0);
+        assert_eq!(test(&["test", ""]), 1);
+        assert_eq!(test(&["test", "!", ""]), 0);
+        assert_eq!(test(&["test", "-n", "-a"]), 0);
+        assert_eq!(test(&["test", "!", "a", "=", "b"]), 0);
+        assert_eq!(test(&["test", "(", "a", ")"]), 0);
+        assert_eq!(test(&["[", "a", "=", "a", "]"]), 0);
+        assert_eq!(test(&["[", "a", "=", "a"]), 2);
+    }
+
+    #[test]
+    fn combines_longer_expressions(){
+        assert_eq!(test(&["test", "a", "=", "a", "-a", "b", "=", "c"]), 1);
+        assert_eq!(test(&["test", "a", "=", "b", "-o", "1", "-lt", "2"]), 0);
+        assert_eq!(test(&["test", "x", "-o", "", "-a", ""]), 0);
+        assert_eq!(test(&["test", "(", "a", "=", "b", "-o", "c", ")", "-a", "d"]), 0);
+    }
+
+    #[test]
+    fn compares_integers_and_strings(){
+        assert_eq!(test(&["test", " 5 ", "-eq", "5"]), 0);
+        assert_eq!(test(&["test", "-3", "-lt", "2"]), 0);
+        assert_eq!(test(&["test", "5", "-eq", "a"]), 2);
+        assert_eq!(test(&["test", "a", "<", "b"]), 0);
+        assert_eq!(test(&["test", "abc", "!=", "abc"]), 1);
+    }
+
+    #[test]
+    fn sets_bash_rematch(){
+        let mut shell = Shell::new();
+        assert_eq!(regex_matches("^([a-z]+)([0-9]+)?-(x)$", "abc-x", &mut shell), Ok(true));
+        assert_eq!(shell.vars.get_element("BASH_REMATCH", 1).as_deref(), Some("abc"));
+        assert_eq!(shell.vars.get_element("BASH_REMATCH", 2).as_deref(), Some(""));
+        assert_eq!(shell.vars.get_element("BASH_REMATCH", 3).as_deref(), Some("x"));
+        assert_eq!(regex_matches("^y", "abc", &mut shell), Ok(false));
+        assert_eq!(shell.vars.get_element("BASH_REMATCH", 0), None);
+        assert_eq!(regex_matches("(", "abc", &mut shell), Err(String::new()));
+    }
+
+    #[test]
+    fn slices_multibyte_matches_safely(){
+        let mut shell = Shell::new();
+        assert_eq!(regex_matches("^.", "é", &mut shell), Ok(true));
+        assert_eq!(regex_matches("é(.)", "xéy", &mut shell), Ok(true));
+        assert_eq!(shell.vars.get_element("BASH_REMATCH", 1).as_deref(), Some("y"));
+    }
+
+    #[test]
+    fn counts_groups_outside_brackets(){
+        assert_eq!(count_groups(b"(a)(b(c))"), 3);
+        assert_eq!(count_groups(b"\\(a[(]"), 0);
+        assert_eq!(count_groups(b"[]()][[:alpha:]](x)"), 1);
+    }
+}
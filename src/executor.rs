@@ -1,26 +1,832 @@
-use std::{env, process::Command};
+use std::{env, path::PathBuf, process::Command};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::rc::Rc;
 use crate::HistoryAction;
 
-use which::which;
+use which::which_in;
 
-use crate::{CommandResult, ParsedResult};
+use crate::arith::evaluate;
+use crate::conditional::{run_conditional, run_test};
+use crate::ast::{self, AndOrList, AndOrOp, CaseItem, CaseTerminator, CompoundCommand, Program, Pipeline, Redirect, SimpleCommand};
+use crate::expand::{expand_assignment_value, expand_pattern, expand_word, expand_words};
+use crate::glob::{pattern_matches, GlobOptions};
+use crate::jobs::enter_process_group;
+use crate::signals;
+use crate::redirect::open_redirections;
+use crate::parser::{is_name, parse_program, RESERVED_WORDS};
+use crate::shell::{LoopControl, Shell, ShellOptions, TrapCondition};
+use crate::vars::{declaration, Value};
+use crate::CommandResult;
 
-/// Executes a single command (built-in or external) and handles output, errors, and history.
+/// How deeply functions may call each other when `FUNCNEST` is not set,
+/// so that runaway recursion fails instead of overflowing the stack.
+const MAX_FUNCTION_DEPTH: usize = 1000;
+
+/// Executes every and-or list of a program in order, stopping early when
+/// `exit`, `break` or `continue` is run. Traps for signals caught meanwhile
+/// run after each list. With `set -n` a non-interactive shell runs nothing,
+/// so scripts are only checked for syntax errors.
+///
+/// # Arguments
+/// * `program` - The parsed command line
+/// * `shell` - Shell state, updated with the status of every pipeline
+pub fn run_program(program: &Program, shell: &mut Shell){
+    for list in &program.lists {
+        if shell.options.noexec && !shell.interactive {
+            break;
+        }
+        if list.background {
+            run_background(list, shell);
+        }else{
+            run_and_or(list, shell);
+        }
+        run_pending_traps(shell);
+        if shell.interrupted() {
+            break;
+        }
+    }
+}
+
+/// Runs a command substitution in a forked copy of the shell and collects
+/// what it writes to stdout. `$?` is set to the status of the commands.
+///
+/// # Arguments
+/// * `program` - The commands inside `$(...)` or backquotes
+/// * `shell` - Shell state, copied into the child
+///
+/// # Returns
+/// * `String` - The output with trailing newlines removed
+pub fn capture_output(program: &Program, shell: &mut Shell)-> String{
+    let (read_end, write_end) = match create_pipe() {
+        Ok(pipe) => pipe,
+        Err(e) => {
+            eprintln!("Error while creating pipe: {}", e);
+            shell.last_status = 1;
+            return String::new();
+        }
+    };
+    let _ = io::stdout().flush();
+    let pid = match unsafe { libc::fork() } {
+        0 => {
+            signals::restore_defaults();
+            enter_subshell(shell);
+            drop(read_end);
+            unsafe { libc::dup2(write_end.as_raw_fd(), 1) };
+            drop(write_end);
+            run_program(program, shell);
+            exit_subshell(shell);
+        },
+        -1 => {
+            eprintln!("Error while trying to fork: {}", io::Error::last_os_error());
+            shell.last_status = 1;
+            return String::new();
+        },
+        pid => pid,
+    };
+    drop(write_end);
+    let mut output = Vec::new();
+    if let Err(e) = File::from(read_end).read_to_end(&mut output) {
+        eprintln!("Error while reading command output: {}", e);
+    }
+    shell.last_status = wait_for_pid(pid);
+    shell.substitution_status = Some(shell.last_status);
+    let output = String::from_utf8_lossy(&output);
+    output.trim_end_matches('\n').to_string()
+}
+
+/// Starts an and-or list ended by `&` as a background job: a forked copy of
+/// the shell in its own process group runs it while the shell goes on.
+/// Without job control its standard input is `/dev/null` and it ignores
+/// `SIGINT` and `SIGQUIT`.
+fn run_background(list: &AndOrList, shell: &mut Shell){
+    let mut command = list.first.text.clone();
+    for (op, pipeline) in &list.rest {
+        command.push_str(if *op == AndOrOp::And { " && " } else { " || " });
+        command.push_str(&pipeline.text);
+    }
+    let _ = io::stdout().flush();
+    match unsafe { libc::fork() } {
+        0 => {
+            enter_process_group(shell.jobs.terminal(), 0, false);
+            if !shell.jobs.enabled() {
+                signals::ignore_interrupts();
+                if let Ok(null) = File::open("/dev/null") {
+                    unsafe { libc::dup2(null.as_raw_fd(), 0) };
+                }
+            }
+            enter_subshell(shell);
+            run_and_or(list, shell);
+            exit_subshell(shell);
+        },
+        -1 => {
+            eprintln!("Error while trying to fork: {}", io::Error::last_os_error());
+            shell.set_pipeline_status(vec![1]);
+        },
+        pid => {
+            shell.jobs.place(pid, pid);
+            let id = shell.jobs.add(pid, &[pid], &command);
+            shell.last_background_pid = Some(pid);
+            if shell.interactive {
+                eprintln!("[{}] {}", id, pid);
+            }
+            shell.set_pipeline_status(vec![0]);
+        },
+    }
+}
+
+/// Runs the pipelines of an and-or list, skipping a pipeline after `&&`
+/// when the status so far is non-zero and after `||` when it is zero.
+fn run_and_or(list: &AndOrList, shell: &mut Shell){
+    run_negatable_pipeline(&list.first, list.rest.is_empty(), shell);
+    for (index, (op, pipeline)) in list.rest.iter().enumerate() {
+        if shell.interrupted() {
+            return;
+        }
+        let succeeded = shell.last_status == 0;
+        let last = index + 1 == list.rest.len();
+        match op {
+            AndOrOp::And if succeeded => run_negatable_pipeline(pipeline, last, shell),
+            AndOrOp::Or if !succeeded => run_negatable_pipeline(pipeline, last, shell),
+            _ => {},
+        }
+    }
+}
+
+/// Runs a pipeline and records its status, inverted when it starts with `!`.
+/// `PIPESTATUS` keeps the statuses of the stages as they were. A failure
+/// runs the `ERR` trap and, with `set -e`, exits the shell, unless the
+/// pipeline is tested by `!`, `&&` or `||` (`last` tells it is not) or by an
+/// enclosing condition.
+fn run_negatable_pipeline(pipeline: &Pipeline, last: bool, shell: &mut Shell){
+    let tested = pipeline.negated || !last;
+    if tested {
+        shell.conditions += 1;
+    }
+    let statuses = run_pipeline(pipeline, shell);
+    if tested {
+        shell.conditions -= 1;
+    }
+    shell.set_pipeline_status(statuses);
+    if pipeline.negated {
+        shell.last_status = if shell.last_status == 0 { 1 } else { 0 };
+        return;
+    }
+    let failed = shell.last_status != 0 && !tested && shell.conditions == 0 && reports_failure(pipeline);
+    if failed && shell.call_stack.is_empty() && !shell.interrupted() {
+        run_trap(TrapCondition::Err, shell);
+    }
+    if failed && shell.options.errexit {
+        shell.exit_requested = true;
+    }
+}
+
+/// Whether a failing pipeline counts as a failure of its own. Compound
+/// commands that run their commands in this shell do not: the command that
+/// failed inside them already did.
+fn reports_failure(pipeline: &Pipeline)-> bool{
+    match pipeline.commands.as_slice() {
+        [ast::Command::Compound(command, _)] => matches!(command, CompoundCommand::Subshell(_) | CompoundCommand::Arithmetic(_) | CompoundCommand::Conditional(_)),
+        _ => true,
+    }
+}
+
+/// Runs a pipeline. A single command runs in the current shell; otherwise
+/// every stage is forked and connected to the next one by an OS pipe, so all
+/// stages run concurrently and data is streamed rather than buffered.
+///
+/// # Arguments
+/// * `pipeline` - The pipeline to run
+/// * `shell` - Shell state
+///
+/// # Returns
+/// * `Vec<i32>` - The exit status of every stage, in pipeline order
+pub fn run_pipeline(pipeline: &Pipeline, shell: &mut Shell)-> Vec<i32>{
+    let stage_count = pipeline.commands.len();
+    shell.jobs.foreground = pipeline.text.clone();
+    if stage_count == 1 {
+        return vec![run_command(&pipeline.commands[0], shell, false)];
+    }
+    let mut pids = Vec::new();
+    let mut pgid = 0;
+    let mut previous_read: Option<OwnedFd> = None;
+
+    for command in &pipeline.commands {
+        let stdin = previous_read.take();
+        let (next_read, stdout) = if pids.len() < stage_count - 1 {
+            match create_pipe(){
+                Ok((read_end, write_end)) => (Some(read_end), Some(write_end)),
+                Err(e) => {
+                    eprintln!("Error while creating pipe: {}", e);
+                    break;
+                }
+            }
+        }else{
+            (None, None)
+        };
+
+        let _ = io::stdout().flush();
+        match unsafe { libc::fork() } {
+            0 => {
+                enter_process_group(shell.jobs.terminal(), pgid, true);
+                enter_subshell(shell);
+                drop(next_read);
+                if let Some(fd) = &stdin {
+                    unsafe { libc::dup2(fd.as_raw_fd(), 0) };
+                }
+                if let Some(fd) = &stdout {
+                    unsafe { libc::dup2(fd.as_raw_fd(), 1) };
+                }
+                drop(stdin);
+                drop(stdout);
+                shell.last_status = run_command(command, shell, true);
+                exit_subshell(shell);
+            },
+            -1 => {
+                eprintln!("Error while trying to fork: {}", io::Error::last_os_error());
+                break;
+            },
+            pid => {
+                if pgid == 0 {
+                    pgid = pid;
+                }
+                shell.jobs.place(pid, pgid);
+                pids.push(pid);
+            },
+        }
+        previous_read = next_read;
+    }
+    drop(previous_read);
+
+    // Stages that could not be started count as failed.
+    let mut statuses = shell.jobs.wait_foreground(&pids, pgid);
+    statuses.resize(stage_count, 1);
+    statuses
+}
+
+/// Runs one stage of a pipeline and returns its exit status.
+///
+/// # Arguments
+/// * `command` - The command to run
+/// * `shell` - Shell state
+/// * `replace_process` - Whether this process is a forked child with nothing
+///   left to do, so an external command may replace it instead of being spawned
+fn run_command(command: &ast::Command, shell: &mut Shell, replace_process: bool)-> i32{
+    match command {
+        ast::Command::Simple(simple) => run_simple_command(simple, shell, replace_process),
+        ast::Command::Compound(compound, redirects) => run_compound_command(compound, redirects, shell),
+        ast::Command::Function(name, body) => {
+            shell.functions.insert(name.clone(), Rc::new((**body).clone()));
+            0
+        },
+    }
+}
+
+/// Runs a compound command in the current shell, with its redirections
+/// applied around all of it.
+fn run_compound_command(command: &CompoundCommand, redirects: &[Redirect], shell: &mut Shell)-> i32{
+    let redirections = match open_redirections(redirects, shell){
+        Ok(redirections) => redirections,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let _saved = match redirections.apply_in_shell() {
+        Ok(saved) => saved,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    match command {
+        CompoundCommand::Arithmetic(expression) => match evaluate_word(expression, shell) {
+            Ok(value) => if value != 0 { 0 } else { 1 },
+            Err(e) => {
+                eprintln!("{}", e);
+                1
+            }
+        },
+        CompoundCommand::BraceGroup(body) => {
+            run_program(body, shell);
+            shell.last_status
+        },
+        CompoundCommand::Subshell(body) => run_subshell(body, shell),
+        CompoundCommand::Conditional(expression) => run_conditional(expression, shell),
+        CompoundCommand::If { branches, else_body } => {
+            for (condition, body) in branches {
+                shell.conditions += 1;
+                run_program(condition, shell);
+                shell.conditions -= 1;
+                if shell.interrupted() {
+                    return shell.last_status;
+                }
+                if shell.last_status == 0 {
+                    run_program(body, shell);
+                    return shell.last_status;
+                }
+            }
+            match else_body {
+                Some(body) => {
+                    run_program(body, shell);
+                    shell.last_status
+                },
+                None => 0,
+            }
+        },
+        CompoundCommand::While { until, condition, body } => {
+            shell.loop_depth += 1;
+            let mut status = 0;
+            loop {
+                shell.conditions += 1;
+                run_program(condition, shell);
+                shell.conditions -= 1;
+                if !finish_iteration(shell) || (shell.last_status == 0) == *until {
+                    break;
+                }
+                run_program(body, shell);
+                status = shell.last_status;
+                if !finish_iteration(shell) {
+                    break;
+                }
+            }
+            shell.loop_depth -= 1;
+            status
+        },
+        CompoundCommand::For { name, words, body } => {
+            let values = match expand_words(words, shell) {
+                Ok(values) => values,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    return 1;
+                }
+            };
+            shell.loop_depth += 1;
+            let mut status = 0;
+            for value in values {
+                if let Err(e) = shell.vars.set(name, value) {
+                    eprintln!("{}", e);
+                    status = 1;
+                    break;
+                }
+                run_program(body, shell);
+                status = shell.last_status;
+                if !finish_iteration(shell) {
+                    break;
+                }
+            }
+            shell.loop_depth -= 1;
+            status
+        },
+        CompoundCommand::Case { word, items } => run_case(word, items, shell),
+        CompoundCommand::ArithmeticFor { init, condition, step, body } => {
+            shell.loop_depth += 1;
+            let status = run_arithmetic_for(init, condition, step, body, shell);
+            shell.loop_depth -= 1;
+            status
+        },
+    }
+}
+
+/// Runs a list in a forked copy of the shell and waits for it. The copy
+/// starts with the same variables, functions and directory, and whatever it
+/// changes, including through `exit`, stays in it.
+fn run_subshell(body: &Program, shell: &mut Shell)-> i32{
+    let _ = io::stdout().flush();
+    match unsafe { libc::fork() } {
+        0 => {
+            enter_process_group(shell.jobs.terminal(), 0, true);
+            enter_subshell(shell);
+            run_program(body, shell);
+            exit_subshell(shell);
+        },
+        -1 => {
+            eprintln!("Error while trying to fork: {}", io::Error::last_os_error());
+            1
+        },
+        pid => {
+            shell.jobs.place(pid, pid);
+            shell.jobs.wait_foreground(&[pid], pid)[0]
+        },
+    }
+}
+
+/// Runs the body of the first `case` item with a pattern matching the word,
+/// then carries on as its terminator says. The status is that of the last
+/// body run, or 0 when none matches.
+fn run_case(word: &ast::Word, items: &[CaseItem], shell: &mut Shell)-> i32{
+    let subject = match expand_word(word, shell) {
+        Ok(subject) => subject,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let mut status = 0;
+    let mut falling_through = false;
+    for item in items {
+        if !falling_through {
+            let mut matched = false;
+            for pattern in &item.patterns {
+                match expand_pattern(pattern, shell) {
+                    Ok(pattern) if pattern_matches(&pattern, &subject, shell.glob_options.extglob) => {
+                        matched = true;
+                        break;
+                    },
+                    Ok(_) => {},
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        return 1;
+                    }
+                }
+            }
+            if !matched {
+                continue;
+            }
+        }
+        status = if item.body.lists.is_empty() { 0 } else {
+            run_program(&item.body, shell);
+            shell.last_status
+        };
+        if shell.interrupted() {
+            break;
+        }
+        match item.terminator {
+            CaseTerminator::Break => break,
+            CaseTerminator::FallThrough => falling_through = true,
+            CaseTerminator::Continue => falling_through = false,
+        }
+    }
+    status
+}
+
+/// Runs the loop of `for ((init; condition; step))`. An error in one of the
+/// expressions ends the loop with status 1.
+fn run_arithmetic_for(init: &ast::Word, condition: &ast::Word, step: &ast::Word, body: &Program, shell: &mut Shell)-> i32{
+    let mut status = 0;
+    if let Err(e) = evaluate_word(init, shell) {
+        eprintln!("{}", e);
+        return 1;
+    }
+    loop {
+        let result = if condition.parts.is_empty() { Ok(1) } else { evaluate_word(condition, shell) };
+        match result {
+            Ok(0) => break,
+            Ok(_) => {},
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        }
+        run_program(body, shell);
+        status = shell.last_status;
+        if !finish_iteration(shell) {
+            break;
+        }
+        if let Err(e) = evaluate_word(step, shell) {
+            eprintln!("{}", e);
+            return 1;
+        }
+    }
+    status
+}
+
+/// Deals with a pending `break` or `continue` at the end of an iteration
+/// and tells whether the loop goes on. One that applies to outer loops is
+/// passed on with one level fewer.
+fn finish_iteration(shell: &mut Shell)-> bool{
+    match shell.loop_control.take() {
+        None => !shell.interrupted(),
+        Some(LoopControl::Continue(1)) => true,
+        Some(LoopControl::Break(1)) => false,
+        Some(LoopControl::Break(levels)) => {
+            shell.loop_control = Some(LoopControl::Break(levels - 1));
+            false
+        },
+        Some(LoopControl::Continue(levels)) => {
+            shell.loop_control = Some(LoopControl::Continue(levels - 1));
+            false
+        },
+    }
+}
+
+/// Expands an arithmetic expression and evaluates it.
+fn evaluate_word(expression: &ast::Word, shell: &mut Shell)-> Result<i64, String>{
+    let expression = expand_word(expression, shell)?;
+    evaluate(&expression, shell)
+}
+
+/// Expands and runs a simple command: a built-in, an external program, or
+/// only assignments and redirections.
+fn run_simple_command(command: &SimpleCommand, shell: &mut Shell, replace_process: bool)-> i32{
+    if shell.call_stack.is_empty() {
+        run_trap(TrapCondition::Debug, shell);
+    }
+    shell.substitution_status = None;
+    let argv = match expand_words(&command.words, shell) {
+        Ok(argv) => argv,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let redirections = match open_redirections(&command.redirects, shell){
+        Ok(redirections) => redirections,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let mut assignments = Vec::new();
+    let mut traced = Vec::new();
+    for assignment in &command.assignments {
+        let value = match expand_assignment_value(&assignment.value, shell) {
+            Ok(value) => value,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        if shell.options.xtrace {
+            traced.push(format!("{}={}", assignment.name, quote(&value)));
+        }
+        if argv.is_empty() {
+            if let Err(e) = shell.vars.set(&assignment.name, value) {
+                eprintln!("{}", e);
+                // Like an unbound variable, this ends a non-interactive shell.
+                if !shell.interactive {
+                    shell.exit_requested = true;
+                }
+                return 1;
+            }
+        }else if shell.vars.get(&assignment.name).is_some_and(|variable| variable.readonly) {
+            eprintln!("{}: readonly variable", assignment.name);
+            return 1;
+        }else{
+            assignments.push((assignment.name.clone(), value));
+        }
+    }
+
+    if shell.options.xtrace {
+        if argv.is_empty() {
+            for assignment in &traced {
+                trace(std::slice::from_ref(assignment), shell);
+            }
+        }else{
+            traced.extend(argv.iter().map(|arg| quote(arg)));
+            trace(&traced, shell);
+        }
+    }
+    if argv.is_empty() {
+        return shell.substitution_status.unwrap_or(0);
+    }
+
+    // A `PATH=... cmd` prefix applies to the lookup of the command as well.
+    let search_path = assignments.iter().rev()
+        .find(|(name, _)| name == "PATH")
+        .map(|(_, value)| value.clone())
+        .or_else(|| shell.vars.get_scalar("PATH"));
+    let function = shell.functions.get(&argv[0]).cloned();
+    let path = if function.is_some() { None } else { find_command(&argv[0], search_path) };
+    if function.is_some() || shell.built_ins.contains(&argv[0]) || path.is_none() {
+        let _saved = match redirections.apply_in_shell() {
+            Ok(saved) => saved,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        };
+        // Prefix assignments only last for the duration of the built-in or
+        // function.
+        let saved_vars: Vec<_> = assignments.iter().map(|(name, _)| (name.clone(), shell.vars.save(name))).collect();
+        for (name, value) in assignments {
+            let _ = shell.vars.set(&name, value);
+            shell.vars.set_exported(&name, true);
+        }
+        let status = match function {
+            Some(body) => call_function(&argv, &body, shell),
+            None => match run_builtin(&argv, shell) {
+                CommandResult::Output(output, error_output, status) => {
+                    write_output(&output, &error_output, shell);
+                    status
+                },
+                CommandResult::NoOp(status) => status,
+                CommandResult::Exit(status) => {
+                    shell.exit_requested = true;
+                    status
+                },
+                CommandResult::ModifyHistory(path, action) => {
+                    shell.history_actions.push((path, action));
+                    0
+                },
+            },
+        };
+        for (name, variable) in saved_vars.into_iter().rev() {
+            shell.vars.restore(&name, variable);
+        }
+        return status;
+    }
+
+    let mut child_command = Command::new(path.unwrap_or_default());
+    child_command.arg0(&argv[0])
+        .args(&argv[1..])
+        .env_clear()
+        .envs(shell.vars.environment())
+        .envs(assignments);
+    let terminal = shell.jobs.terminal();
+    unsafe {
+        child_command.pre_exec(move || {
+            enter_process_group(terminal, 0, true);
+            redirections.apply()
+        });
+    }
+    if replace_process {
+        let e = child_command.exec();
+        eprintln!("{}: {}", argv[0], e);
+        return 126;
+    }
+    match child_command.spawn() {
+        Ok(child) => {
+            let pid = child.id() as libc::pid_t;
+            shell.jobs.place(pid, pid);
+            shell.jobs.wait_foreground(&[pid], pid)[0]
+        },
+        Err(e) => {
+            eprintln!("{}: {}", argv[0], e);
+            126
+        },
+    }
+}
+
+/// Prints a command for `set -x`: `PS4`, `+ ` by default, followed by its
+/// words as quoted by the caller.
+fn trace(words: &[String], shell: &Shell){
+    let prefix = shell.vars.get_scalar("PS4").unwrap_or_else(|| "+ ".to_string());
+    eprintln!("{}{}", prefix, words.join(" "));
+}
+
+/// Quotes a value so that the shell would read it back unchanged, the way
+/// `set` lists variables.
+fn quote(value: &str)-> String{
+    if !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || "_-./:,+@%=".contains(c)) {
+        return value.to_string();
+    }
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// Calls a shell function with `argv[1..]` as its positional parameters.
+/// Variables declared `local` in it are restored when it returns, and
+/// `FUNCNAME` lists the functions being run, innermost first. A `RETURN`
+/// trap set while it ran runs as it returns.
+///
+/// # Arguments
+/// * `argv` - The function name and its arguments
+/// * `body` - The compound command the function was defined with
+/// * `shell` - Shell state
+///
+/// # Returns
+/// * `i32` - The status given to `return`, or else that of the body
+fn call_function(argv: &[String], body: &ast::Command, shell: &mut Shell)-> i32{
+    let limit = shell.vars.get_scalar("FUNCNEST")
+        .and_then(|value| value.parse::<usize>().ok())
+        .filter(|limit| *limit > 0)
+        .unwrap_or(MAX_FUNCTION_DEPTH);
+    if shell.call_stack.len() >= limit {
+        eprintln!("{}: maximum function nesting level exceeded ({})", argv[0], limit);
+        return 1;
+    }
+    let positional_params = std::mem::replace(&mut shell.positional_params, argv[1..].to_vec());
+    let loop_depth = std::mem::replace(&mut shell.loop_depth, 0);
+    shell.call_stack.push(argv[0].clone());
+    shell.local_scopes.push(Vec::new());
+    set_funcname(shell);
+    let return_trap = shell.traps.get(&TrapCondition::Return).cloned();
+
+    let status = run_command(body, shell, false);
+
+    shell.returning = false;
+    if shell.traps.get(&TrapCondition::Return) != return_trap.as_ref() {
+        shell.last_status = status;
+        run_trap(TrapCondition::Return, shell);
+    }
+    for (name, variable) in shell.local_scopes.pop().unwrap_or_default().into_iter().rev() {
+        shell.vars.restore(&name, variable);
+    }
+    shell.call_stack.pop();
+    set_funcname(shell);
+    shell.loop_depth = loop_depth;
+    shell.positional_params = positional_params;
+    status
+}
+
+/// Makes `FUNCNAME` reflect the call stack; it is unset outside functions.
+fn set_funcname(shell: &mut Shell){
+    if shell.call_stack.is_empty() {
+        shell.vars.restore("FUNCNAME", None);
+    }else{
+        let names = shell.call_stack.iter().rev().cloned().collect();
+        let _ = shell.vars.set_array("FUNCNAME", names);
+    }
+}
+
+/// Runs the command of a trap, if one is set and it is not already running a
+/// trap. `$?` is kept unless the command runs `exit`.
+fn run_trap(condition: TrapCondition, shell: &mut Shell){
+    if shell.running_trap {
+        return;
+    }
+    let Some(action) = shell.traps.get(&condition).filter(|action| !action.is_empty()).cloned() else {
+        return;
+    };
+    let program = match parse_program(&action) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("trap: {}", e);
+            return;
+        }
+    };
+    let status = shell.last_status;
+    shell.running_trap = true;
+    run_program(&program, shell);
+    shell.running_trap = false;
+    if !shell.exit_requested {
+        shell.last_status = status;
+    }
+}
+
+/// Runs the traps of the signals that arrived since the last time. A signal
+/// caught only so that the `EXIT` trap runs ends the shell after it.
+fn run_pending_traps(shell: &mut Shell){
+    for signal in signals::take_pending() {
+        if signals::caught_as_fatal(signal) {
+            run_exit_trap(shell);
+            signals::die_by(signal);
+        }
+        run_trap(TrapCondition::Signal(signal), shell);
+    }
+}
+
+/// Runs the `EXIT` trap, once, as the shell or a subshell exits.
+///
+/// # Arguments
+/// * `shell` - Shell state; `last_status` is the status to exit with and
+///   may be changed by `exit` in the trap
+pub fn run_exit_trap(shell: &mut Shell){
+    shell.exit_requested = false;
+    shell.returning = false;
+    shell.loop_control = None;
+    run_trap(TrapCondition::Exit, shell);
+    shell.traps.remove(&TrapCondition::Exit);
+}
+
+/// Sets up a forked copy of the shell: job control is off and traps that
+/// run commands are reset, while ignored signals stay ignored.
+fn enter_subshell(shell: &mut Shell){
+    shell.jobs.disable();
+    shell.traps.retain(|_, action| action.is_empty());
+}
+
+/// Ends a forked copy of the shell with its last status, after its `EXIT`
+/// trap.
+fn exit_subshell(shell: &mut Shell)-> !{
+    run_exit_trap(shell);
+    exit_child(shell.last_status)
+}
+
+/// Looks up an external command in the directories of a `PATH` value; names
+/// containing a `/` are used as they are.
+fn find_command(name: &str, search_path: Option<String>)-> Option<PathBuf>{
+    let cwd = env::current_dir().ok()?;
+    which_in(name, search_path, cwd).ok()
+}
+
+/// Executes a built-in command and handles output, errors, and history.
 ///
 /// # Arguments
 /// * `command` - The command and its arguments
-/// * `parsed_result` - Parsed command line info (for redirection)
-/// * `built_ins` - List of built-in commands
-/// * `history` - Command history
+/// * `shell` - Shell state (built-in list, history, and the status `exit` defaults to)
 ///
 /// # Returns
-/// * `CommandResult` - The result of command execution
-pub fn run_command(command: &Vec<String>, parsed_result: &ParsedResult, built_ins: &Vec<String>, history: &Vec<String>)-> CommandResult{
+/// * `CommandResult` - The result of command execution, including its exit status
+pub fn run_builtin(command: &[String], shell: &mut Shell)-> CommandResult{
     let mut output = String::new();
-    let mut error_output = String::new();
-    if command.is_empty() {return CommandResult::NoOp;}
+    let error_output = String::new();
+    if command.is_empty() {return CommandResult::NoOp(0);}
     match command[0].as_str(){
-        "exit" => return CommandResult::Exit,
+        "exit" => {
+            match command.get(1) {
+                None => CommandResult::Exit(shell.last_status),
+                Some(arg) => match arg.parse::<i64>() {
+                    Ok(code) => CommandResult::Exit((code & 0xff) as i32),
+                    Err(_) => {
+                        eprintln!("exit: {}: numeric argument required", arg);
+                        CommandResult::Exit(2)
+                    }
+                }
+            }
+        },
         "echo" => {
             for (i,word) in command.iter().enumerate().skip(1){
                 let mut chars = word.chars().peekable();
@@ -30,9 +836,9 @@ pub fn run_command(command: &Vec<String>, parsed_result: &ParsedResult, built_in
                         if chars.peek().is_some(){
                             let escaped = chars.next().unwrap();
                             match escaped{
-                                'n' => output.push_str("\n"),
-                                't' => output.push_str("\t"),
-                                '\\' => output.push_str("\\"),
+                                'n' => output.push('\n'),
+                                't' => output.push('\t'),
+                                '\\' => output.push('\\'),
                                 _ => {
                                     output.push('\\');
                                     output.push(escaped);
@@ -50,59 +856,62 @@ pub fn run_command(command: &Vec<String>, parsed_result: &ParsedResult, built_in
                 }
             }
             output.push('\n');
-            return CommandResult::Output(output,error_output);
+            CommandResult::Output(output,error_output,0)
         },
         "type" => {
             if command.len() < 2{
                 eprintln!("Usage: type <command>");
-                return CommandResult::Output(output,error_output);
+                return CommandResult::Output(output,error_output,2);
             }
             let cmd = &command[1];
-            if built_ins.iter().any(|s| s == cmd) {
+            if RESERVED_WORDS.contains(&cmd.as_str()) {
+                output = format!("{} is a shell keyword\n", cmd)
+            } else if shell.functions.contains_key(cmd) {
+                output = format!("{} is a function\n", cmd)
+            } else if shell.built_ins.iter().any(|s| s == cmd) {
                 output = format!("{} is a shell builtin\n", cmd)
-            } else if let Ok(path) = which(cmd) {
+            } else if let Some(path) = find_command(cmd, shell.vars.get_scalar("PATH")) {
                 output = format!("{} is {}\n", cmd, path.display())
             } else {
-                output = format!("{}: not found\n", cmd)
+                output = format!("{}: not found\n", cmd);
+                return CommandResult::Output(output,error_output,1);
             }
-            return CommandResult::Output(output,error_output);
+            CommandResult::Output(output,error_output,0)
         },
         "pwd" =>{
             match env::current_dir() {
                 Ok(path) => output = format!("{}\n", path.display()),
                 Err(e) => {
                     eprintln!("Error while displaying the path: {}",e);
-                    return CommandResult::Output(output,error_output);
+                    return CommandResult::Output(output,error_output,1);
                 },
             }
-            return CommandResult::Output(output,error_output);
+            CommandResult::Output(output,error_output,0)
         },
         "cd" => {
             if command.len() != 2 {
                 eprintln!("Usage: cd <directory>");
-                return CommandResult::NoOp;
+                return CommandResult::NoOp(2);
             }
-            let target = if command[1] == "~" {
-                match env::var("HOME"){
-                    Ok(home) => home,
-                    Err(e) => {
-                        eprintln!("HOME not set: {}", e);
-                        return CommandResult::NoOp;
-                    }
-                }
-            }else{
-                command[1].clone()    
-            };
-            if let Err(_) = env::set_current_dir(&target){
+            let previous = shell.vars.get_scalar("PWD")
+                .or_else(|| env::current_dir().ok().map(|path| path.display().to_string()));
+            if env::set_current_dir(&command[1]).is_err(){
                 eprintln!("cd: {}: No such file or directory", command[1]);
+                return CommandResult::NoOp(1);
             }
-            return CommandResult::NoOp;
+            if let Some(previous) = previous {
+                let _ = shell.vars.set("OLDPWD", previous);
+            }
+            if let Ok(path) = env::current_dir() {
+                let _ = shell.vars.set("PWD", path.display().to_string());
+            }
+            CommandResult::NoOp(0)
         },
         "history" => {
             if command.len() >= 2 && command[1].starts_with('-') {
                 if command.len() > 3 {
                     eprintln!("Usage: history -r/-a/-w [file]");
-                    return CommandResult::Output(output, error_output);
+                    return CommandResult::Output(output, error_output, 2);
                 }
                 let action = match command[1].as_str() {
                     "-r" => HistoryAction::Read,
@@ -110,62 +919,792 @@ pub fn run_command(command: &Vec<String>, parsed_result: &ParsedResult, built_in
                     "-a" => HistoryAction::Append,
                     _ => {
                         eprintln!("history: {}: invalid option", command[1]);
-                        return CommandResult::Output(output, error_output);
+                        return CommandResult::Output(output, error_output, 2);
                     }
                 };
-                let path = command.get(2).map(|s| s.clone());
+                let path = command.get(2).cloned();
                 return CommandResult::ModifyHistory(path, action);
             }
             if command.len() == 1 {
-                for (i, entry) in history.iter().enumerate() {
+                for (i, entry) in shell.history.iter().enumerate() {
                     output.push_str(&format!("{}  {}\n", i + 1, entry));
                 }
             } else if command.len() == 2 {
                 let limit = match command[1].parse::<usize>() {
-                    Ok(n) => n.min(history.len()),
+                    Ok(n) => n.min(shell.history.len()),
                     Err(_) => {
                         eprintln!("history: {}: numeric argument required", command[1]);
-                        return CommandResult::Output(output, error_output);
+                        return CommandResult::Output(output, error_output, 2);
                     }
                 };
-                let start_index = history.len().saturating_sub(limit);
-                for (i, entry) in history.iter().enumerate().skip(start_index) {
+                let start_index = shell.history.len().saturating_sub(limit);
+                for (i, entry) in shell.history.iter().enumerate().skip(start_index) {
                     output.push_str(&format!("{}  {}\n", i + 1, entry));
                 }
             } else {
                 eprintln!("Usage: history [n] or history -r/-a/-w [file]");
+                return CommandResult::Output(output, error_output, 2);
             }
             
-            return CommandResult::Output(output, error_output);
+            CommandResult::Output(output, error_output, 0)
         },
-        _ => {
-            let cmd = &command[0];
-            if let Ok(_path) = which(cmd) {
-                let result = Command::new(cmd)
-                    .args(&command[1..])
-                    .output();
-                match result {
-                    Ok(result_out) => {
-                        output = format!("{}", String::from_utf8_lossy(&result_out.stdout));
-                        if !result_out.stderr.is_empty() {
-                            error_output = format!("{}", String::from_utf8_lossy(&result_out.stderr));
-                        }
-                        if !parsed_result.redirect_as_output && !parsed_result.append_as_output && !output.is_empty(){
-                            if !output.ends_with('\n'){
-                                println!()
+        "let" => {
+            if command.len() < 2 {
+                eprintln!("let: expression expected");
+                return CommandResult::Output(output, error_output, 1);
+            }
+            let mut value = 0;
+            for expression in &command[1..] {
+                match evaluate(expression, shell) {
+                    Ok(result) => value = result,
+                    Err(e) => {
+                        eprintln!("let: {}", e);
+                        return CommandResult::Output(output, error_output, 1);
+                    }
+                }
+            }
+            CommandResult::Output(output, error_output, if value != 0 { 0 } else { 1 })
+        },
+        "export" | "readonly" => {
+            let mut unexport = false;
+            let mut names = Vec::new();
+            for arg in &command[1..] {
+                match arg.as_str() {
+                    "-p" if names.is_empty() => {},
+                    "-n" if names.is_empty() && command[0] == "export" => unexport = true,
+                    _ if arg.starts_with('-') && names.is_empty() => {
+                        eprintln!("{}: {}: invalid option", command[0], arg);
+                        return CommandResult::Output(output, error_output, 2);
+                    },
+                    _ => names.push(arg),
+                }
+            }
+            if names.is_empty() {
+                for (name, variable) in shell.vars.sorted() {
+                    let listed = if command[0] == "export" { variable.exported } else { variable.readonly };
+                    if listed {
+                        output.push_str(&declaration(name, variable));
+                        output.push('\n');
+                    }
+                }
+                return CommandResult::Output(output, error_output, 0);
+            }
+            let mut status = 0;
+            for arg in names {
+                let (name, value) = match arg.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (arg.as_str(), None),
+                };
+                if !is_name(name) {
+                    eprintln!("{}: `{}': not a valid identifier", command[0], arg);
+                    status = 1;
+                    continue;
+                }
+                if let Some(value) = value {
+                    if let Err(e) = shell.vars.set(name, value.to_string()) {
+                        eprintln!("{}: {}", command[0], e);
+                        status = 1;
+                        continue;
+                    }
+                }
+                if command[0] == "export" {
+                    shell.vars.set_exported(name, !unexport);
+                }else{
+                    shell.vars.set_readonly(name);
+                }
+            }
+            CommandResult::Output(output, error_output, status)
+        },
+        "unset" => {
+            let mut functions = false;
+            let mut explicit = false;
+            let mut status = 0;
+            for arg in &command[1..] {
+                match arg.as_str() {
+                    "-v" => {
+                        functions = false;
+                        explicit = true;
+                    },
+                    "-f" => functions = true,
+                    _ if functions => { shell.functions.remove(arg); },
+                    _ if !is_name(arg) => {
+                        eprintln!("unset: `{}': not a valid identifier", arg);
+                        status = 1;
+                    },
+                    // Without `-v`, a name that is not a variable may be a function.
+                    _ if !explicit && shell.vars.get(arg).is_none() => { shell.functions.remove(arg); },
+                    _ => if let Err(e) = shell.vars.unset(arg) {
+                        eprintln!("unset: {}", e);
+                        status = 1;
+                    },
+                }
+            }
+            CommandResult::Output(output, error_output, status)
+        },
+        "break" | "continue" => {
+            let levels = match command.get(1).map(|arg| arg.parse::<i64>()) {
+                None => 1,
+                Some(Ok(levels)) if levels >= 1 => levels as usize,
+                Some(Ok(_)) => {
+                    eprintln!("{}: {}: loop count out of range", command[0], command[1]);
+                    return CommandResult::Output(output, error_output, 1);
+                },
+                Some(Err(_)) => {
+                    eprintln!("{}: {}: numeric argument required", command[0], command[1]);
+                    return CommandResult::Output(output, error_output, 1);
+                },
+            };
+            if shell.loop_depth == 0 {
+                eprintln!("{}: only meaningful in a `for', `while', or `until' loop", command[0]);
+                return CommandResult::NoOp(0);
+            }
+            let levels = levels.min(shell.loop_depth);
+            shell.loop_control = Some(if command[0] == "break" { LoopControl::Break(levels) } else { LoopControl::Continue(levels) });
+            CommandResult::NoOp(0)
+        },
+        "local" => {
+            if shell.local_scopes.is_empty() {
+                eprintln!("local: can only be used in a function");
+                return CommandResult::Output(output, error_output, 1);
+            }
+            let mut status = 0;
+            for arg in &command[1..] {
+                let (name, value) = match arg.split_once('=') {
+                    Some((name, value)) => (name, Some(value)),
+                    None => (arg.as_str(), None),
+                };
+                if !is_name(name) {
+                    eprintln!("local: `{}': not a valid identifier", arg);
+                    status = 1;
+                    continue;
+                }
+                if shell.vars.get(name).is_some_and(|variable| variable.readonly) {
+                    eprintln!("local: {}: readonly variable", name);
+                    status = 1;
+                    continue;
+                }
+                let scope = shell.local_scopes.last_mut().expect("checked above");
+                if !scope.iter().any(|(local, _)| local == name) {
+                    scope.push((name.to_string(), shell.vars.save(name)));
+                    shell.vars.restore(name, None);
+                }
+                if let Some(value) = value {
+                    let _ = shell.vars.set(name, value.to_string());
+                }
+            }
+            CommandResult::Output(output, error_output, status)
+        },
+        "return" => {
+            if shell.call_stack.is_empty() {
+                eprintln!("return: can only `return' from a function or sourced script");
+                return CommandResult::Output(output, error_output, 1);
+            }
+            shell.returning = true;
+            match command.get(1) {
+                None => CommandResult::NoOp(shell.last_status),
+                Some(arg) => match arg.parse::<i64>() {
+                    Ok(code) => CommandResult::NoOp((code & 0xff) as i32),
+                    Err(_) => {
+                        eprintln!("return: {}: numeric argument required", arg);
+                        CommandResult::NoOp(2)
+                    },
+                },
+            }
+        },
+        "shift" => {
+            let count = match command.get(1).map(|arg| arg.parse::<i64>()) {
+                None => 1,
+                Some(Ok(count)) if count >= 0 => count as usize,
+                Some(Ok(_)) => {
+                    eprintln!("shift: {}: shift count out of range", command[1]);
+                    return CommandResult::Output(output, error_output, 1);
+                },
+                Some(Err(_)) => {
+                    eprintln!("shift: {}: numeric argument required", command[1]);
+                    return CommandResult::Output(output, error_output, 1);
+                },
+            };
+            if count > shell.positional_params.len() {
+                return CommandResult::NoOp(1);
+            }
+            shell.positional_params.drain(..count);
+            CommandResult::NoOp(0)
+        },
+        "set" => {
+            if command.len() < 2 {
+                for (name, variable) in shell.vars.sorted() {
+                    if let Some(Value::Scalar(value)) = &variable.value {
+                        output.push_str(&format!("{}={}\n", name, quote(value)));
+                    }
+                }
+                return CommandResult::Output(output, error_output, 0);
+            }
+            let mut index = 1;
+            let mut end_of_options = false;
+            while let Some(arg) = command.get(index) {
+                if arg == "--" || arg == "-" {
+                    // A lone `-` also turns off `-x`.
+                    if arg == "-" {
+                        shell.options.xtrace = false;
+                    }
+                    end_of_options = true;
+                    index += 1;
+                    break;
+                }
+                let Some(letters) = arg.strip_prefix(['-', '+']) else {
+                    break;
+                };
+                let enable = arg.starts_with('-');
+                index += 1;
+                for letter in letters.chars() {
+                    if letter != 'o' {
+                        let Some(option) = shell.options.letter_mut(letter) else {
+                            eprintln!("set: {}{}: invalid option", &arg[..1], letter);
+                            eprintln!("set: usage: set [-efnuxC] [-o option-name] [--] [arg ...]");
+                            return CommandResult::Output(output, error_output, 2);
+                        };
+                        *option = enable;
+                        continue;
+                    }
+                    let Some(name) = command.get(index) else {
+                        for (name, _) in ShellOptions::NAMES {
+                            let on = shell.options.option_mut(name).is_some_and(|option| *option);
+                            if enable {
+                                output.push_str(&format!("{:<15}\t{}\n", name, if on { "on" } else { "off" }));
+                            }else{
+                                output.push_str(&format!("set {}o {}\n", if on { "-" } else { "+" }, name));
                             }
                         }
-                        return CommandResult::Output(output,error_output);
+                        continue;
+                    };
+                    index += 1;
+                    match shell.options.option_mut(name) {
+                        Some(option) => *option = enable,
+                        None => {
+                            eprintln!("set: {}: invalid option name", name);
+                            return CommandResult::Output(output, error_output, 1);
+                        },
                     }
+                }
+            }
+            if end_of_options || index < command.len() {
+                shell.positional_params = command[index..].to_vec();
+            }
+            CommandResult::Output(output, error_output, 0)
+        },
+        "jobs" => {
+            let mut with_pid = false;
+            let mut pids_only = false;
+            let mut specs = Vec::new();
+            for arg in &command[1..] {
+                match arg.as_str() {
+                    "-l" => with_pid = true,
+                    "-p" => pids_only = true,
+                    _ if arg.starts_with('-') && specs.is_empty() => {
+                        eprintln!("jobs: {}: invalid option", arg);
+                        return CommandResult::Output(output, error_output, 2);
+                    },
+                    _ => specs.push(arg.as_str()),
+                }
+            }
+            shell.jobs.update();
+            let mut status = 0;
+            let ids = if specs.is_empty() {
+                shell.jobs.ids()
+            }else{
+                specs.iter().filter_map(|spec| match shell.jobs.resolve(Some(spec), "jobs") {
+                    Ok(id) => Some(id),
                     Err(e) => {
-                        eprintln!("Error: {}", e);
-                        return CommandResult::Output(output,error_output);
+                        eprintln!("{}", e);
+                        status = 1;
+                        None
                     },
+                }).collect()
+            };
+            for job in ids.iter().filter_map(|&id| shell.jobs.get(id)) {
+                if pids_only {
+                    output.push_str(&format!("{}\n", job.pgid));
+                }else{
+                    output.push_str(&shell.jobs.format_job(job, with_pid));
+                    output.push('\n');
+                }
+            }
+            shell.jobs.mark_reported();
+            CommandResult::Output(output, error_output, status)
+        },
+        "fg" | "bg" => {
+            if !shell.jobs.enabled() {
+                eprintln!("{}: no job control", command[0]);
+                return CommandResult::Output(output, error_output, 1);
+            }
+            shell.jobs.update();
+            let specs: Vec<Option<&str>> = if command.len() < 2 || command[0] == "fg" {
+                vec![command.get(1).map(|spec| spec.as_str())]
+            }else{
+                command[1..].iter().map(|spec| Some(spec.as_str())).collect()
+            };
+            let mut status = 0;
+            for spec in specs {
+                let id = match shell.jobs.resolve(spec, &command[0]) {
+                    Ok(id) => id,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        status = 1;
+                        continue;
+                    }
+                };
+                if command[0] == "fg" {
+                    status = shell.jobs.foreground(id);
+                }else if shell.jobs.get(id).is_some_and(|job| !job.is_stopped()) {
+                    eprintln!("bg: job {} already in background", id);
+                }else{
+                    shell.jobs.background(id);
                 }
-            } else {
-                output = format!("{}: not found\n", cmd);
-                return CommandResult::Output(output,error_output);
             }
+            CommandResult::NoOp(status)
+        },
+        "disown" => {
+            let mut status = 0;
+            if command.get(1).map(|arg| arg.as_str()) == Some("-a") {
+                for id in shell.jobs.ids() {
+                    shell.jobs.remove(id);
+                }
+                return CommandResult::NoOp(0);
+            }
+            let specs: Vec<Option<&str>> = if command.len() < 2 {
+                vec![None]
+            }else{
+                command[1..].iter().map(|spec| Some(spec.as_str())).collect()
+            };
+            for spec in specs {
+                match shell.jobs.resolve(spec, "disown") {
+                    Ok(id) => { shell.jobs.remove(id); },
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        status = 1;
+                    },
+                }
+            }
+            CommandResult::NoOp(status)
+        },
+        "wait" => {
+            if command.len() < 2 {
+                for id in shell.jobs.ids() {
+                    shell.jobs.wait_for(id);
+                }
+                return CommandResult::NoOp(0);
+            }
+            let mut status = 0;
+            for spec in &command[1..] {
+                status = match shell.jobs.resolve(Some(spec), "wait") {
+                    Ok(id) => shell.jobs.wait_for(id),
+                    Err(_) if !spec.starts_with('%') => {
+                        eprintln!("wait: pid {} is not a child of this shell", spec);
+                        127
+                    },
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        127
+                    },
+                };
+            }
+            CommandResult::NoOp(status)
+        },
+        "trap" => {
+            let mut args = &command[1..];
+            let mut print = false;
+            while let Some(arg) = args.first() {
+                match arg.as_str() {
+                    "--" => {
+                        args = &args[1..];
+                        break;
+                    },
+                    "-p" => print = true,
+                    "-l" => {
+                        for (index, (name, number)) in signals::SIGNAL_NAMES.iter().enumerate() {
+                            let separator = if index % 5 == 4 || index + 1 == signals::SIGNAL_NAMES.len() { "\n" } else { "\t" };
+                            output.push_str(&format!("{:2}) SIG{}{}", number, name, separator));
+                        }
+                        return CommandResult::Output(output, error_output, 0);
+                    },
+                    arg if arg.starts_with('-') && arg.len() > 1 => {
+                        eprintln!("trap: {}: invalid option", arg);
+                        eprintln!("trap: usage: trap [-lp] [[action] signal_spec ...]");
+                        return CommandResult::Output(output, error_output, 2);
+                    },
+                    _ => break,
+                }
+                args = &args[1..];
+            }
+            let mut status = 0;
+            if print || args.is_empty() {
+                let conditions: Vec<_> = if args.is_empty() {
+                    shell.traps.keys().copied().collect()
+                }else{
+                    args.iter().filter_map(|spec| {
+                        let condition = TrapCondition::parse(spec);
+                        if condition.is_none() {
+                            eprintln!("trap: {}: invalid signal specification", spec);
+                            status = 1;
+                        }
+                        condition
+                    }).collect()
+                };
+                for condition in conditions {
+                    if let Some(action) = shell.traps.get(&condition) {
+                        output.push_str(&format!("trap -- '{}' {}\n", action.replace('\'', "'\\''"), condition.name()));
+                    }
+                }
+                return CommandResult::Output(output, error_output, status);
+            }
+            if args.len() == 1 && TrapCondition::parse(&args[0]).is_none() {
+                eprintln!("trap: usage: trap [-lp] [[action] signal_spec ...]");
+                return CommandResult::Output(output, error_output, 2);
+            }
+            // A lone condition, or ones starting with a number, are reset.
+            let (action, specs) = if args.len() == 1 || args[0].parse::<u32>().is_ok() {
+                ("-", args)
+            }else{
+                (args[0].as_str(), &args[1..])
+            };
+            for spec in specs {
+                let Some(condition) = TrapCondition::parse(spec) else {
+                    eprintln!("trap: {}: invalid signal specification", spec);
+                    status = 1;
+                    continue;
+                };
+                if action == "-" {
+                    shell.traps.remove(&condition);
+                }else{
+                    shell.traps.insert(condition, action.to_string());
+                }
+                match condition {
+                    TrapCondition::Signal(signal) if action == "-" => signals::reset(signal),
+                    TrapCondition::Signal(signal) if action.is_empty() => signals::ignore(signal),
+                    TrapCondition::Signal(signal) => signals::trap(signal),
+                    TrapCondition::Exit => signals::catch_fatal(action != "-" && !action.is_empty()),
+                    _ => {},
+                }
+            }
+            CommandResult::NoOp(status)
+        },
+        "test" | "[" => CommandResult::NoOp(run_test(command, shell)),
+        "shopt" => {
+            let mut setting = None;
+            let mut print_commands = false;
+            let mut quiet = false;
+            let mut names = Vec::new();
+            for arg in &command[1..] {
+                match arg.as_str() {
+                    "-s" => setting = Some(true),
+                    "-u" => setting = Some(false),
+                    "-p" => print_commands = true,
+                    "-q" => quiet = true,
+                    _ if arg.starts_with('-') => {
+                        eprintln!("shopt: {}: invalid option", arg);
+                        eprintln!("shopt: usage: shopt [-pqsu] [optname ...]");
+                        return CommandResult::Output(output, error_output, 2);
+                    },
+                    _ => names.push(arg.as_str()),
+                }
+            }
+            if let Some(name) = names.iter().find(|name| shell.glob_options.option_mut(name).is_none()) {
+                eprintln!("shopt: {}: invalid shell option name", name);
+                return CommandResult::Output(output, error_output, 1);
+            }
+            if let Some(value) = setting {
+                for name in &names {
+                    if let Some(option) = shell.glob_options.option_mut(name) {
+                        *option = value;
+                    }
+                }
+                if !names.is_empty() {
+                    return CommandResult::Output(output, error_output, 0);
+                }
+            }
+            let listed: Vec<&str> = if names.is_empty() { GlobOptions::NAMES.to_vec() } else { names };
+            let mut status = 0;
+            for name in listed {
+                let enabled = shell.glob_options.option_mut(name).is_some_and(|option| *option);
+                if setting.is_some_and(|value| value != enabled) {
+                    continue;
+                }
+                if !enabled {
+                    status = 1;
+                }
+                if quiet {
+                    continue;
+                }
+                if print_commands {
+                    output.push_str(&format!("shopt {} {}\n", if enabled { "-s" } else { "-u" }, name));
+                }else{
+                    output.push_str(&format!("{:<15}\t{}\n", name, if enabled { "on" } else { "off" }));
+                }
+            }
+            CommandResult::Output(output, error_output, status)
+        },
+        _ => {
+            output = format!("{}: not found\n", command[0]);
+            CommandResult::Output(output, error_output, 127)
+        }
+    }
+}
+
+/// Writes the captured output of a built-in to the shell's stdout and
+/// stderr, wherever they currently point.
+///
+/// # Arguments
+/// * `output` - Text the built-in produced on stdout
+/// * `error_output` - Text the built-in produced on stderr
+/// * `shell` - Shell state, to tell a forked copy of the shell from the shell itself
+fn write_output(output: &str, error_output: &str, shell: &Shell){
+    let mut stdout = io::stdout();
+    match stdout.write_all(output.as_bytes()).and_then(|_| stdout.flush()) {
+        // A forked copy of the shell whose reader has gone away ends as if
+        // killed by `SIGPIPE`, so that loops like `while :; do echo; done |
+        // head -1` finish even when that signal is ignored.
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe && shell.pid != std::process::id() => {
+            exit_child(128 + libc::SIGPIPE)
+        },
+        Err(e) => eprintln!("Error while writing output: {}", e),
+        Ok(()) => {},
+    }
+    if let Err(e) = io::stderr().write_all(error_output.as_bytes()) {
+        eprintln!("Error while writing output: {}", e);
+    }
+}
+
+/// Waits for the process `pid` and returns its status, `128 + n` if it was
+/// killed by signal n.
+fn wait_for_pid(pid: libc::pid_t)-> i32{
+    let mut status = 0;
+    while unsafe { libc::waitpid(pid, &mut status, 0) } == -1 {
+        if io::Error::last_os_error().kind() != io::ErrorKind::Interrupted {
+            return 127;
         }
     }
-}
\ No newline at end of file
+    if libc::WIFSIGNALED(status) {
+        128 + libc::WTERMSIG(status)
+    }else{
+        libc::WEXITSTATUS(status)
+    }
+}
+
+/// Terminates a forked child after flushing what it wrote to stdout.
+fn exit_child(status: i32)-> !{
+    let _ = io::stdout().flush();
+    unsafe { libc::_exit(status) }
+}
+
+/// Creates a pipe whose ends are closed automatically on exec.
+fn create_pipe()-> io::Result<(OwnedFd, OwnedFd)>{
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(line: &str, shell: &mut Shell){
+        run_program(&parse_program(line).unwrap(), shell);
+    }
+
+    fn var(shell: &Shell, name: &str)-> Option<String>{
+        shell.vars.get_scalar(name)
+    }
+
+    /// Runs a built-in, returning what it printed and its status.
+    fn builtin(args: &[&str], shell: &mut Shell)-> (String, i32){
+        let argv: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        match run_builtin(&argv, shell) {
+            CommandResult::Output(output, _, status) => (output, status),
+            CommandResult::NoOp(status) => (String::new(), status),
+            _ => panic!("unexpected result"),
+        }
+    }
+
+    #[test]
+    fn restores_local_variables_on_return(){
+        let shell = &mut Shell::new();
+        run("x=global; export e=1; g() { seen=$x; }", shell);
+        run("f() { local x=inner e n=new; g; inside=$e; return 0; x=after; }; f", shell);
+        assert_eq!(var(shell, "seen").as_deref(), Some("inner"));
+        assert_eq!(var(shell, "inside").as_deref(), Some(""));
+        assert_eq!(var(shell, "x").as_deref(), Some("global"));
+        assert_eq!(var(shell, "e").as_deref(), Some("1"));
+        assert!(shell.vars.get("e").is_some_and(|variable| variable.exported));
+        assert_eq!(var(shell, "n"), None);
+        assert!(shell.local_scopes.is_empty());
+    }
+
+    #[test]
+    fn restores_locals_of_every_level(){
+        let shell = &mut Shell::new();
+        run("x=0; f() { local x=$1; if ((x < 3)); then f $((x + 1)); fi; levels=$levels$x; }; f 1", shell);
+        assert_eq!(var(shell, "levels").as_deref(), Some("321"));
+        assert_eq!(var(shell, "x").as_deref(), Some("0"));
+        run("local y=1", shell);
+        assert_eq!(shell.last_status, 1);
+    }
+
+    #[test]
+    fn return_without_a_status_keeps_the_last_one(){
+        let shell = &mut Shell::new();
+        run("f() { test -z x; return; }; f", shell);
+        assert_eq!(shell.last_status, 1);
+        run("f() { return 300; }; f", shell);
+        assert_eq!(shell.last_status, 44);
+        run("f() { for i in 1 2; do return $i; done; ran=yes; }; f", shell);
+        assert_eq!(shell.last_status, 1);
+        assert_eq!(var(shell, "ran"), None);
+        run("return", shell);
+        assert_eq!(shell.last_status, 1);
+        assert!(!shell.returning);
+    }
+
+    #[test]
+    fn shifts_positional_parameters(){
+        let shell = &mut Shell::new();
+        run("set -- a b c d", shell);
+        assert_eq!(shell.positional_params, ["a", "b", "c", "d"]);
+        run("shift", shell);
+        assert_eq!(shell.positional_params, ["b", "c", "d"]);
+        run("shift 2", shell);
+        assert_eq!(shell.positional_params, ["d"]);
+        for out_of_range in ["shift 2", "shift -1", "shift x"] {
+            run(out_of_range, shell);
+            assert_eq!(shell.last_status, 1);
+            assert_eq!(shell.positional_params, ["d"]);
+        }
+        run("shift 0", shell);
+        assert_eq!(shell.last_status, 0);
+        run("shift 1", shell);
+        assert!(shell.positional_params.is_empty());
+        run("shift", shell);
+        assert_eq!(shell.last_status, 1);
+    }
+
+    #[test]
+    fn sets_positional_parameters(){
+        let shell = &mut Shell::new();
+        run("set -- -x 'b c'", shell);
+        assert_eq!(shell.positional_params, ["-x", "b c"]);
+        assert!(!shell.options.xtrace);
+        run("set --", shell);
+        assert!(shell.positional_params.is_empty());
+        run("set a b", shell);
+        assert_eq!(shell.positional_params, ["a", "b"]);
+        run("set -u", shell);
+        assert!(shell.options.nounset);
+        assert_eq!(shell.positional_params, ["a", "b"]);
+        run("set -e -- x", shell);
+        assert!(shell.options.errexit);
+        assert_eq!(shell.positional_params, ["x"]);
+    }
+
+    #[test]
+    fn sets_options_given_together(){
+        let shell = &mut Shell::new();
+        assert_eq!(builtin(&["set", "-euo", "pipefail", "a"], shell).1, 0);
+        assert!(shell.options.errexit && shell.options.nounset && shell.options.pipefail);
+        assert_eq!(shell.positional_params, ["a"]);
+        assert_eq!(builtin(&["set", "+eo", "pipefail", "-C"], shell).1, 0);
+        assert!(!shell.options.errexit && !shell.options.pipefail);
+        assert!(shell.options.nounset && shell.options.noclobber);
+        assert_eq!(shell.positional_params, ["a"]);
+        assert_eq!(builtin(&["set", "-x", "-"], shell).1, 0);
+        assert!(!shell.options.xtrace);
+        assert!(shell.positional_params.is_empty());
+        assert_eq!(builtin(&["set", "-eq"], shell).1, 2);
+        assert_eq!(builtin(&["set", "-o", "nosuch"], shell).1, 1);
+    }
+
+    #[test]
+    fn lists_options_for_set_o(){
+        let shell = &mut Shell::new();
+        builtin(&["set", "-e", "-o", "pipefail"], shell);
+        assert_eq!(builtin(&["set", "-o"], shell).0, concat!(
+            "errexit        \ton\n", "noclobber      \toff\n", "noexec         \toff\n", "noglob         \toff\n",
+            "nounset        \toff\n", "pipefail       \ton\n", "xtrace         \toff\n",
+        ));
+        assert_eq!(builtin(&["set", "+o"], shell).0, concat!(
+            "set -o errexit\n", "set +o noclobber\n", "set +o noexec\n", "set +o noglob\n",
+            "set +o nounset\n", "set -o pipefail\n", "set +o xtrace\n",
+        ));
+        assert_eq!(builtin(&["set", "+o"], shell).0.lines().count(), ShellOptions::NAMES.len());
+    }
+
+    #[test]
+    fn parses_trap_conditions(){
+        for spec in ["INT", "int", "SIGINT", "SigInt", "2"] {
+            assert_eq!(TrapCondition::parse(spec), Some(TrapCondition::Signal(libc::SIGINT)));
+        }
+        assert_eq!(TrapCondition::parse("EXIT"), Some(TrapCondition::Exit));
+        assert_eq!(TrapCondition::parse("exit"), Some(TrapCondition::Exit));
+        assert_eq!(TrapCondition::parse("0"), Some(TrapCondition::Exit));
+        assert_eq!(TrapCondition::parse("DEBUG"), Some(TrapCondition::Debug));
+        assert_eq!(TrapCondition::parse("err"), Some(TrapCondition::Err));
+        assert_eq!(TrapCondition::parse("RETURN"), Some(TrapCondition::Return));
+        for invalid in ["SIGEXIT", "SIGFOO", "FOO", "64", "-1", ""] {
+            assert_eq!(TrapCondition::parse(invalid), None);
+        }
+        assert_eq!(TrapCondition::Signal(libc::SIGUSR1).name(), "SIGUSR1");
+        assert_eq!(TrapCondition::Exit.name(), "EXIT");
+    }
+
+    #[test]
+    fn sets_lists_and_resets_traps(){
+        let shell = &mut Shell::new();
+        assert_eq!(builtin(&["trap", "echo hi", "USR1", "EXIT"], shell), (String::new(), 0));
+        assert_eq!(builtin(&["trap", "echo 'a'", "DEBUG"], shell), (String::new(), 0));
+        let listing = "trap -- 'echo hi' EXIT\ntrap -- 'echo hi' SIGUSR1\ntrap -- 'echo '\\''a'\\''' DEBUG\n";
+        assert_eq!(builtin(&["trap"], shell).0, listing);
+        assert_eq!(builtin(&["trap", "-p"], shell).0, listing);
+        assert_eq!(builtin(&["trap", "-p", "SIGUSR1", "ERR"], shell).0, "trap -- 'echo hi' SIGUSR1\n");
+
+        assert_eq!(builtin(&["trap", "-", "USR1"], shell).1, 0);
+        assert_eq!(builtin(&["trap", "DEBUG"], shell).1, 0);
+        assert_eq!(builtin(&["trap", "-p"], shell).0, "trap -- 'echo hi' EXIT\n");
+        assert_eq!(builtin(&["trap", "0"], shell).1, 0);
+        assert!(shell.traps.is_empty());
+
+        builtin(&["trap", "", "USR1"], shell);
+        assert_eq!(builtin(&["trap", "-p", "USR1"], shell).0, "trap -- '' SIGUSR1\n");
+        builtin(&["trap", "-", "USR1"], shell);
+        assert!(shell.traps.is_empty());
+    }
+
+    #[test]
+    fn rejects_invalid_trap_arguments(){
+        let shell = &mut Shell::new();
+        assert_eq!(builtin(&["trap", "echo", "FOO", "ERR"], shell).1, 1);
+        assert_eq!(shell.traps.get(&TrapCondition::Err).map(String::as_str), Some("echo"));
+        assert_eq!(builtin(&["trap", "FOO"], shell).1, 2);
+        assert_eq!(builtin(&["trap", "-z"], shell).1, 2);
+        assert_eq!(builtin(&["trap", "-p", "FOO"], shell).1, 1);
+        builtin(&["trap", "-", "ERR"], shell);
+        assert!(shell.traps.is_empty());
+    }
+
+    #[test]
+    fn limits_recursion(){
+        let shell = &mut Shell::new();
+        shell.positional_params = vec!["top".to_string()];
+        run("FUNCNEST=5; d=0; f() { d=$((d + 1)); f x; }; f a", shell);
+        assert_eq!(shell.last_status, 1);
+        assert_eq!(var(shell, "d").as_deref(), Some("5"));
+        assert!(shell.call_stack.is_empty());
+        assert_eq!(shell.positional_params, ["top"]);
+
+        // Without FUNCNEST the limit is MAX_FUNCTION_DEPTH.
+        run("unset FUNCNEST; d=0", shell);
+        shell.call_stack = vec!["outer".to_string(); MAX_FUNCTION_DEPTH - 2];
+        run("f a", shell);
+        assert_eq!(var(shell, "d").as_deref(), Some("2"));
+        assert_eq!(shell.call_stack.len(), MAX_FUNCTION_DEPTH - 2);
+    }
+}
@@ -0,0 +1,910 @@
+use crate::arith::evaluate;
+use crate::ast::{ParamExpansion, ParamOp, ReplaceMode, Word, WordPart};
+use crate::brace::expand_braces;
+use crate::executor::capture_output;
+use crate::glob::{expand_pathname, has_glob_chars, pattern_matches};
+use crate::parser::is_name;
+use crate::shell::Shell;
+use crate::vars::Value;
+
+/// Field separators used when `IFS` is unset.
+const DEFAULT_IFS: &str = " \t\n";
+
+/// Expands the words of a command into its argument list. Braces are
+/// expanded first; then parameters and command substitutions are replaced
+/// by their values, the results of unquoted expansions are split into fields
+/// on `IFS`, fields with unquoted pattern characters become the matching
+/// file names, and quotes are removed.
+///
+/// # Arguments
+/// * `words` - The words as parsed
+/// * `shell` - Shell state parameters are read from (and assigned by `${name:=word}`)
+///
+/// # Returns
+/// * `Result<Vec<String>, String>` - The resulting arguments, or the error
+///   message of a failed expansion such as `${name:?message}`
+pub fn expand_words(words: &[Word], shell: &mut Shell)-> Result<Vec<String>, String>{
+    let mut arguments = Vec::new();
+    for word in words.iter().flat_map(expand_braces) {
+        let word = expand_tilde(&word, false, shell);
+        let mut expansion = Expansion::new(true);
+        expansion.expand_parts(&word.parts, false, shell)?;
+        let separators = field_separators(shell);
+        for field in expansion.finish() {
+            for chars in split_field(field, &separators) {
+                arguments.extend(expand_pathnames(&chars, shell)?);
+            }
+        }
+    }
+    Ok(arguments)
+}
+
+/// Replaces a field containing unquoted pattern characters by the sorted
+/// names of the files it matches. A pattern that matches nothing is kept
+/// as it is, unless `nullglob` or `failglob` is set; with `set -f` every
+/// field is kept as it is.
+fn expand_pathnames(chars: &[ExpChar], shell: &Shell)-> Result<Vec<String>, String>{
+    let options = &shell.glob_options;
+    let pattern = to_pattern(chars);
+    if shell.options.noglob || !has_glob_chars(&pattern, options.extglob) {
+        return Ok(vec![to_string(chars)]);
+    }
+    let paths = expand_pathname(&pattern, options);
+    if !paths.is_empty() {
+        return Ok(paths);
+    }
+    if options.failglob {
+        return Err(format!("no match: {}", to_string(chars)));
+    }
+    if options.nullglob {
+        return Ok(Vec::new());
+    }
+    Ok(vec![to_string(chars)])
+}
+
+/// Expands a word that must stay a single string, such as a redirection
+/// target. No field splitting takes place.
+pub fn expand_word(word: &Word, shell: &mut Shell)-> Result<String, String>{
+    let word = expand_tilde(word, false, shell);
+    let mut expansion = Expansion::new(false);
+    expansion.expand_parts(&word.parts, false, shell)?;
+    Ok(to_string(&expansion.current.chars))
+}
+
+/// Expands the value of a `NAME=value` assignment like `expand_word`, except
+/// that a tilde is also expanded after every `:`, as in `PATH=~/bin:~/.local/bin`.
+pub fn expand_assignment_value(word: &Word, shell: &mut Shell)-> Result<String, String>{
+    expand_word(&expand_tilde(word, true, shell), shell)
+}
+
+/// Replaces the tilde-prefixes of a word (`~`, `~user`, `~+` and `~-`, up to
+/// the first `/`) with the directories they name, quoted so that they are
+/// neither split nor globbed. A prefix is only recognised at the start of
+/// the word, or after a `:` when `assignment` is set, and only when it is
+/// entirely unquoted; one naming an unknown user is left alone.
+fn expand_tilde(word: &Word, assignment: bool, shell: &Shell)-> Word{
+    let mut parts = Vec::new();
+    let mut at_prefix = true;
+    for (i, part) in word.parts.iter().enumerate() {
+        let WordPart::Literal(text) = part else {
+            parts.push(part.clone());
+            at_prefix = false;
+            continue;
+        };
+        let mut literal = String::new();
+        let mut rest = text.as_str();
+        loop {
+            if at_prefix && rest.starts_with('~') {
+                let end = rest.find(|c| c == '/' || (assignment && c == ':')).unwrap_or(rest.len());
+                let complete = end < rest.len() || i + 1 == word.parts.len();
+                if let Some(directory) = complete.then(|| tilde_directory(&rest[1..end], shell)).flatten() {
+                    if !literal.is_empty() {
+                        parts.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(WordPart::SingleQuoted(directory));
+                    rest = &rest[end..];
+                }
+            }
+            at_prefix = false;
+            match rest.find(':').filter(|_| assignment) {
+                Some(colon) => {
+                    literal.push_str(&rest[..=colon]);
+                    rest = &rest[colon + 1..];
+                    at_prefix = true;
+                },
+                None => {
+                    literal.push_str(rest);
+                    break;
+                }
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(WordPart::Literal(literal));
+        }
+    }
+    Word { parts }
+}
+
+/// Resolves the text after a `~`: nothing for `HOME`, `+` for `PWD`, `-` for
+/// `OLDPWD`, or a login name looked up in the password database.
+fn tilde_directory(name: &str, shell: &Shell)-> Option<String>{
+    match name {
+        "" => shell.vars.get_scalar("HOME").or_else(|| user_home(None)),
+        "+" => shell.vars.get_scalar("PWD"),
+        "-" => shell.vars.get_scalar("OLDPWD"),
+        user => user_home(Some(user)),
+    }
+}
+
+/// Looks up the home directory of `user`, or of the current user.
+fn user_home(user: Option<&str>)-> Option<String>{
+    let entry = match user {
+        Some(user) => {
+            let name = std::ffi::CString::new(user).ok()?;
+            unsafe { libc::getpwnam(name.as_ptr()) }
+        },
+        None => unsafe { libc::getpwuid(libc::getuid()) },
+    };
+    if entry.is_null() {
+        return None;
+    }
+    let directory = unsafe { std::ffi::CStr::from_ptr((*entry).pw_dir) };
+    Some(directory.to_string_lossy().into_owned())
+}
+
+/// Expands a word used as a pattern, as in `${name#pattern}` or a `case`
+/// item. Quoted characters are escaped with a backslash so that they only
+/// match themselves.
+///
+/// # Arguments
+/// * `word` - The pattern as parsed
+/// * `shell` - Shell state, for parameters and command substitution
+///
+/// # Returns
+/// * `Result<String, String>` - A pattern for `pattern_matches`, or an expansion error
+pub fn expand_pattern(word: &Word, shell: &mut Shell)-> Result<String, String>{
+    let word = &expand_tilde(word, false, shell);
+    let mut expansion = Expansion::new(false);
+    expansion.expand_parts(&word.parts, false, shell)?;
+    Ok(to_pattern(&expansion.current.chars))
+}
+
+/// Expands the regular expression on the right of `=~` in `[[ ]]`. Quoted
+/// characters that are special in an extended regular expression are
+/// escaped with a backslash so that they only match themselves.
+///
+/// # Arguments
+/// * `word` - The regular expression as parsed
+/// * `shell` - Shell state, for parameters and command substitution
+///
+/// # Returns
+/// * `Result<String, String>` - An extended regular expression, or an expansion error
+pub fn expand_regex(word: &Word, shell: &mut Shell)-> Result<String, String>{
+    let word = &expand_tilde(word, false, shell);
+    let mut expansion = Expansion::new(false);
+    expansion.expand_parts(&word.parts, false, shell)?;
+    Ok(to_regex(&expansion.current.chars))
+}
+
+/// A character of an expanded word and how it came to be there.
+#[derive(Debug, Clone, Copy)]
+struct ExpChar{
+    c: char,
+    /// Written inside quotes or escaped, so it is never special.
+    quoted: bool,
+    /// Produced by an unquoted expansion, so it is subject to field splitting.
+    split: bool
+}
+
+#[derive(Debug, Default)]
+struct Field{
+    chars: Vec<ExpChar>,
+    /// Whether the field contains quotes, so that it is kept even when empty.
+    quoted: bool
+}
+
+/// The value of a parameter before any operator is applied.
+enum ParamValue{
+    Unset,
+    Scalar(String),
+    /// `$@`, `$*` and `name[@]`: several values, joined with the first
+    /// character of `IFS` inside double quotes when `star` is set.
+    List(Vec<String>, bool)
+}
+
+/// Accumulates the fields a single word expands to.
+struct Expansion{
+    fields: Vec<Field>,
+    current: Field,
+    /// Whether lists such as `"$@"` produce separate fields; words that must
+    /// stay a single string join them with spaces instead.
+    separate: bool,
+    /// Set while expanding the word of an unquoted `${name:-word}` or
+    /// `${name:+word}`, whose literal text is split like an expansion.
+    split_literals: bool
+}
+
+impl Expansion{
+    fn new(separate: bool)-> Self{
+        Expansion { fields: Vec::new(), current: Field::default(), separate, split_literals: false }
+    }
+
+    fn expand_parts(&mut self, parts: &[WordPart], in_double: bool, shell: &mut Shell)-> Result<(), String>{
+        for part in parts {
+            match part {
+                WordPart::Literal(text) => self.push_text(text, in_double, self.split_literals && !in_double),
+                WordPart::Escaped(c) => self.current.chars.push(ExpChar { c: *c, quoted: true, split: false }),
+                WordPart::SingleQuoted(text) => {
+                    self.current.quoted = true;
+                    self.push_text(text, true, false);
+                },
+                WordPart::DoubleQuoted(inner) => {
+                    // `"$@"` with no values expands to no field at all.
+                    if !is_list_expansion(inner) {
+                        self.current.quoted = true;
+                    }
+                    self.expand_parts(inner, true, shell)?;
+                },
+                WordPart::Parameter(param) => self.expand_parameter(param, in_double, shell)?,
+                WordPart::CommandSubst(program) => {
+                    let output = capture_output(program, shell);
+                    self.push_text(&output, in_double, !in_double);
+                },
+                WordPart::Arithmetic(expression) => {
+                    let value = match evaluate(&expand_word(expression, shell)?, shell) {
+                        Ok(value) => value,
+                        Err(e) => {
+                            // Unlike `((...))` and `let`, a failed `$((...))`
+                            // ends a non-interactive shell.
+                            if !shell.interactive {
+                                shell.exit_requested = true;
+                            }
+                            return Err(e);
+                        }
+                    };
+                    self.push_text(&value.to_string(), in_double, !in_double);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn expand_parameter(&mut self, param: &ParamExpansion, in_double: bool, shell: &mut Shell)-> Result<(), String>{
+        let value = lookup(param, shell)?;
+        let handles_unset = matches!(param.op, ParamOp::Default { .. } | ParamOp::Alternative { .. } | ParamOp::Assign { .. } | ParamOp::Error { .. });
+        if shell.options.nounset && matches!(value, ParamValue::Unset) && !handles_unset {
+            return Err(unbound_variable(&param.name, shell));
+        }
+        match &param.op {
+            ParamOp::Value => self.push_value(value, in_double, shell),
+            ParamOp::Length => {
+                let length = match value {
+                    ParamValue::Unset => 0,
+                    ParamValue::Scalar(s) => s.chars().count(),
+                    ParamValue::List(values, _) => values.len(),
+                };
+                self.push_text(&length.to_string(), in_double, !in_double);
+            },
+            ParamOp::Default { colon, word } => {
+                if is_set(&value, *colon) {
+                    self.push_value(value, in_double, shell);
+                }else{
+                    self.expand_operand(word, in_double, shell)?;
+                }
+            },
+            ParamOp::Alternative { colon, word } => {
+                if is_set(&value, *colon) {
+                    self.expand_operand(word, in_double, shell)?;
+                }
+            },
+            ParamOp::Assign { colon, word } => {
+                if is_set(&value, *colon) {
+                    self.push_value(value, in_double, shell);
+                }else{
+                    if !is_name(&param.name) || param.index.is_some() {
+                        return Err(format!("${}: cannot assign in this way", param.name));
+                    }
+                    let text = expand_word(word, shell)?;
+                    shell.vars.set(&param.name, text.clone())?;
+                    self.push_text(&text, in_double, !in_double);
+                }
+            },
+            ParamOp::Error { colon, word } => {
+                if is_set(&value, *colon) {
+                    self.push_value(value, in_double, shell);
+                }else{
+                    let message = expand_word(word, shell)?;
+                    let message = if message.is_empty() { "parameter null or not set".to_string() } else { message };
+                    // Like an unbound variable, this ends a non-interactive shell.
+                    if !shell.interactive {
+                        shell.exit_requested = true;
+                    }
+                    return Err(format!("{}: {}", param.name, message));
+                }
+            },
+            ParamOp::RemovePrefix { longest, pattern } => {
+                let pattern = expand_pattern(pattern, shell)?;
+                let extglob = shell.glob_options.extglob;
+                let value = map_value(value, |s| remove_prefix(s, &pattern, *longest, extglob));
+                self.push_value(value, in_double, shell);
+            },
+            ParamOp::RemoveSuffix { longest, pattern } => {
+                let pattern = expand_pattern(pattern, shell)?;
+                let extglob = shell.glob_options.extglob;
+                let value = map_value(value, |s| remove_suffix(s, &pattern, *longest, extglob));
+                self.push_value(value, in_double, shell);
+            },
+            ParamOp::Replace { mode, pattern, replacement } => {
+                let pattern = expand_pattern(pattern, shell)?;
+                let replacement = expand_word(replacement, shell)?;
+                let extglob = shell.glob_options.extglob;
+                let value = map_value(value, |s| replace(s, &pattern, &replacement, *mode, extglob));
+                self.push_value(value, in_double, shell);
+            },
+            ParamOp::Substring { offset, length } => {
+                let offset = evaluate(&expand_word(offset, shell)?, shell)?;
+                let length = match length {
+                    Some(length) => Some(evaluate(&expand_word(length, shell)?, shell)?),
+                    None => None,
+                };
+                let value = match value {
+                    ParamValue::Unset => ParamValue::Unset,
+                    ParamValue::Scalar(s) => {
+                        let chars: Vec<char> = s.chars().collect();
+                        let (start, end) = substring_range(chars.len(), offset, length)?;
+                        ParamValue::Scalar(chars[start..end].iter().collect())
+                    },
+                    ParamValue::List(mut values, star) => {
+                        // The positional parameters count from `$0`.
+                        if param.index.is_none() {
+                            values.insert(0, shell.script_name.clone());
+                        }
+                        let (start, end) = substring_range(values.len(), offset, length)?;
+                        ParamValue::List(values[start..end].to_vec(), star)
+                    },
+                };
+                self.push_value(value, in_double, shell);
+            },
+        }
+        Ok(())
+    }
+
+    /// Expands the word of `${name:-word}` or `${name:+word}` in place of
+    /// the parameter.
+    fn expand_operand(&mut self, word: &Word, in_double: bool, shell: &mut Shell)-> Result<(), String>{
+        let outer = std::mem::replace(&mut self.split_literals, !in_double);
+        let result = self.expand_parts(&word.parts, in_double, shell);
+        self.split_literals = outer;
+        result
+    }
+
+    /// Appends the value of a parameter. Unquoted values are marked for field
+    /// splitting; the values of a list become separate fields unless they are
+    /// joined by `"$*"` or the word must stay a single string.
+    fn push_value(&mut self, value: ParamValue, in_double: bool, shell: &Shell){
+        match value {
+            ParamValue::Unset => {},
+            ParamValue::Scalar(s) => self.push_text(&s, in_double, !in_double),
+            ParamValue::List(values, star) if !self.separate || (in_double && star) => {
+                let separator = if in_double && star {
+                    field_separators(shell).chars().next().map(String::from).unwrap_or_default()
+                }else{
+                    " ".to_string()
+                };
+                self.push_text(&values.join(&separator), in_double, !in_double);
+            },
+            ParamValue::List(values, _) => {
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        self.end_field();
+                    }
+                    self.current.quoted |= in_double;
+                    self.push_text(value, in_double, !in_double);
+                }
+            },
+        }
+    }
+
+    fn push_text(&mut self, text: &str, quoted: bool, split: bool){
+        self.current.chars.extend(text.chars().map(|c| ExpChar { c, quoted, split }));
+    }
+
+    fn end_field(&mut self){
+        self.fields.push(std::mem::take(&mut self.current));
+    }
+
+    fn finish(mut self)-> Vec<Field>{
+        self.end_field();
+        self.fields
+    }
+}
+
+/// Whether the parts of a double-quoted string are only `$@` or `${name[@]}`,
+/// which expand to one field per value rather than one field in total.
+fn is_list_expansion(parts: &[WordPart])-> bool{
+    match parts {
+        [WordPart::Parameter(param)] => {
+            let all_elements = match &param.index {
+                Some(index) => index.as_literal().as_deref() == Some("@"),
+                None => param.name == "@",
+            };
+            all_elements && !matches!(param.op, ParamOp::Length)
+        },
+        _ => false,
+    }
+}
+
+/// Looks up a special parameter, a variable, or an element of an array
+/// variable (`name[i]`, `name[@]` or `name[*]`, where `i` is arithmetic).
+fn lookup(param: &ParamExpansion, shell: &mut Shell)-> Result<ParamValue, String>{
+    let value = match param.name.as_str() {
+        "?" => ParamValue::Scalar(shell.last_status.to_string()),
+        "$" => ParamValue::Scalar(shell.pid.to_string()),
+        "!" => match shell.last_background_pid {
+            Some(pid) => ParamValue::Scalar(pid.to_string()),
+            None => ParamValue::Unset,
+        },
+        "0" => ParamValue::Scalar(shell.script_name.clone()),
+        "#" => ParamValue::Scalar(shell.positional_params.len().to_string()),
+        "-" => ParamValue::Scalar(shell.option_flags()),
+        "@" => ParamValue::List(shell.positional_params.clone(), false),
+        "*" => ParamValue::List(shell.positional_params.clone(), true),
+        name if name.starts_with(|c: char| c.is_ascii_digit()) => {
+            let position = name.parse::<usize>().unwrap_or(0);
+            match shell.positional_params.get(position.wrapping_sub(1)) {
+                Some(value) => ParamValue::Scalar(value.clone()),
+                None => ParamValue::Unset,
+            }
+        },
+        name => {
+            let Some(index) = &param.index else {
+                return Ok(match shell.vars.get_scalar(name) {
+                    Some(value) => ParamValue::Scalar(value),
+                    None => ParamValue::Unset,
+                });
+            };
+            let subscript = index.as_literal();
+            if matches!(subscript.as_deref(), Some("@" | "*")) {
+                let values = match shell.vars.get(name).and_then(|variable| variable.value.as_ref()) {
+                    Some(Value::Array(values)) => values.clone(),
+                    Some(Value::Scalar(value)) => vec![value.clone()],
+                    None => Vec::new(),
+                };
+                ParamValue::List(values, subscript.as_deref() == Some("*"))
+            }else{
+                let index = evaluate(&expand_word(index, shell)?, shell)?;
+                match shell.vars.get_element(name, index) {
+                    Some(value) => ParamValue::Scalar(value),
+                    None => ParamValue::Unset,
+                }
+            }
+        },
+    };
+    Ok(value)
+}
+
+/// The error for an unset parameter expanded under `set -u`, which also
+/// makes a non-interactive shell exit.
+pub fn unbound_variable(name: &str, shell: &mut Shell)-> String{
+    if !shell.interactive {
+        shell.exit_requested = true;
+    }
+    format!("{}: unbound variable", name)
+}
+
+/// Whether a parameter counts as set for `-`, `=`, `?` and `+`; with `colon`
+/// an empty value counts as unset too.
+fn is_set(value: &ParamValue, colon: bool)-> bool{
+    match value {
+        ParamValue::Unset => false,
+        ParamValue::Scalar(s) => !colon || !s.is_empty(),
+        ParamValue::List(values, _) => !values.is_empty() && (!colon || values.iter().any(|v| !v.is_empty())),
+    }
+}
+
+/// Applies a string operation to a value, or to each value of a list.
+fn map_value(value: ParamValue, f: impl Fn(&str)-> String)-> ParamValue{
+    match value {
+        ParamValue::Unset => ParamValue::Unset,
+        ParamValue::Scalar(s) => ParamValue::Scalar(f(&s)),
+        ParamValue::List(values, star) => ParamValue::List(values.iter().map(|v| f(v)).collect(), star),
+    }
+}
+
+/// Byte offsets of every character boundary of `s`, including its end.
+fn boundaries(s: &str)-> Vec<usize>{
+    s.char_indices().map(|(i, _)| i).chain(std::iter::once(s.len())).collect()
+}
+
+fn remove_prefix(value: &str, pattern: &str, longest: bool, extglob: bool)-> String{
+    let mut ends = boundaries(value);
+    if longest {
+        ends.reverse();
+    }
+    match ends.into_iter().find(|&end| pattern_matches(pattern, &value[..end], extglob)) {
+        Some(end) => value[end..].to_string(),
+        None => value.to_string(),
+    }
+}
+
+fn remove_suffix(value: &str, pattern: &str, longest: bool, extglob: bool)-> String{
+    let mut starts = boundaries(value);
+    if !longest {
+        starts.reverse();
+    }
+    match starts.into_iter().find(|&start| pattern_matches(pattern, &value[start..], extglob)) {
+        Some(start) => value[..start].to_string(),
+        None => value.to_string(),
+    }
+}
+
+/// Replaces the longest match of `pattern` as selected by `mode`.
+fn replace(value: &str, pattern: &str, replacement: &str, mode: ReplaceMode, extglob: bool)-> String{
+    let positions = boundaries(value);
+    match mode {
+        ReplaceMode::Prefix => {
+            match positions.iter().rev().find(|&&end| pattern_matches(pattern, &value[..end], extglob)) {
+                Some(&end) => format!("{}{}", replacement, &value[end..]),
+                None => value.to_string(),
+            }
+        },
+        ReplaceMode::Suffix => {
+            match positions.iter().find(|&&start| pattern_matches(pattern, &value[start..], extglob)) {
+                Some(&start) => format!("{}{}", &value[..start], replacement),
+                None => value.to_string(),
+            }
+        },
+        ReplaceMode::First | ReplaceMode::All => {
+            if pattern.is_empty() {
+                return value.to_string();
+            }
+            let mut result = String::new();
+            let mut i = 0;
+            while i + 1 < positions.len() {
+                let start = positions[i];
+                let end = positions[i + 1..].iter().rposition(|&end| pattern_matches(pattern, &value[start..end], extglob));
+                match end {
+                    Some(offset) => {
+                        result.push_str(replacement);
+                        i += offset + 1;
+                        if mode == ReplaceMode::First {
+                            result.push_str(&value[positions[i]..]);
+                            return result;
+                        }
+                    },
+                    None => {
+                        result.push_str(&value[start..positions[i + 1]]);
+                        i += 1;
+                    }
+                }
+            }
+            result
+        },
+    }
+}
+
+/// Converts an offset and optional length, either of which may count from
+/// the end when negative, into a range of `len` items.
+fn substring_range(len: usize, offset: i64, length: Option<i64>)-> Result<(usize, usize), String>{
+    let len = len as i64;
+    let start = if offset < 0 { len + offset } else { offset };
+    if start < 0 || start > len {
+        return Ok((0, 0));
+    }
+    let end = match length {
+        None => len,
+        Some(length) if length < 0 => {
+            let end = len + length;
+            if end < start {
+                return Err(format!("{}: substring expression < 0", length));
+            }
+            end
+        },
+        Some(length) => (start + length).min(len),
+    };
+    Ok((start as usize, end as usize))
+}
+
+/// The value of `IFS`, or the default separators when it is unset.
+fn field_separators(shell: &Shell)-> String{
+    shell.vars.get_scalar("IFS").unwrap_or_else(|| DEFAULT_IFS.to_string())
+}
+
+/// Splits a field on the `IFS` characters produced by unquoted expansions.
+/// Runs of IFS whitespace separate fields and are ignored at either end;
+/// every other IFS character separates fields by itself, together with any
+/// whitespace around it.
+fn split_field(field: Field, separators: &str)-> Vec<Vec<ExpChar>>{
+    let is_separator = |ch: &ExpChar| ch.split && separators.contains(ch.c);
+    let is_blank = |ch: &ExpChar| is_separator(ch) && matches!(ch.c, ' ' | '\t' | '\n');
+    let chars = field.chars;
+    let mut pieces = Vec::new();
+    let mut piece = Vec::new();
+    let mut i = 0;
+    while i < chars.len() && is_blank(&chars[i]) {
+        i += 1;
+    }
+    while i < chars.len() {
+        if !is_separator(&chars[i]) {
+            piece.push(chars[i]);
+            i += 1;
+            continue;
+        }
+        pieces.push(std::mem::take(&mut piece));
+        let blank = is_blank(&chars[i]);
+        i += 1;
+        while i < chars.len() && is_blank(&chars[i]) {
+            i += 1;
+        }
+        if blank && i < chars.len() && is_separator(&chars[i]) {
+            i += 1;
+            while i < chars.len() && is_blank(&chars[i]) {
+                i += 1;
+            }
+        }
+    }
+    if !piece.is_empty() {
+        pieces.push(piece);
+    }
+    if pieces.is_empty() && field.quoted {
+        pieces.push(Vec::new());
+    }
+    pieces
+}
+
+/// Builds a pattern from expanded characters, escaping the quoted ones.
+fn to_pattern(chars: &[ExpChar])-> String{
+    let mut pattern = String::new();
+    for ch in chars {
+        if ch.quoted {
+            pattern.push('\\');
+        }
+        pattern.push(ch.c);
+    }
+    pattern
+}
+
+/// Builds an extended regular expression from expanded characters,
+/// escaping the quoted ones that would otherwise be special.
+fn to_regex(chars: &[ExpChar])-> String{
+    let mut regex = String::new();
+    for ch in chars {
+        if ch.quoted && "\\.[](){}*+?^$|".contains(ch.c) {
+            regex.push('\\');
+        }
+        regex.push(ch.c);
+    }
+    regex
+}
+
+fn to_string(chars: &[ExpChar])-> String{
+    chars.iter().map(|ch| ch.c).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Command;
+    use crate::parser::parse_program;
+
+    /// The words of `echo` followed by `line`, as parsed.
+    fn words(line: &str)-> Vec<Word>{
+        let program = parse_program(&format!("echo {}", line)).unwrap();
+        match &program.lists[0].first.commands[0] {
+            Command::Simple(command) => command.words[1..].to_vec(),
+            _ => panic!("not a simple command"),
+        }
+    }
+
+    fn expand(line: &str, shell: &mut Shell)-> Result<Vec<String>, String>{
+        expand_words(&words(line), shell)
+    }
+
+    /// A shell where `v` is `hello`, `e` is empty and `u` is unset.
+    fn test_shell()-> Shell{
+        let mut shell = Shell::new();
+        shell.vars.set("v", "hello".to_string()).unwrap();
+        shell.vars.set("e", String::new()).unwrap();
+        shell.vars.unset("u").unwrap();
+        shell.vars.unset("IFS").unwrap();
+        shell
+    }
+
+    /// The value of `P=text` after expansion.
+    fn assignment(text: &str, shell: &mut Shell)-> String{
+        let program = parse_program(&format!("P={}", text)).unwrap();
+        match &program.lists[0].first.commands[0] {
+            Command::Simple(command) => expand_assignment_value(&command.assignments[0].value, shell).unwrap(),
+            _ => panic!("not a simple command"),
+        }
+    }
+
+    #[test]
+    fn expands_tildes_at_the_start_of_words(){
+        let shell = &mut test_shell();
+        shell.vars.set("HOME", "/home/me".to_string()).unwrap();
+        shell.vars.set("PWD", "/here".to_string()).unwrap();
+        shell.vars.set("OLDPWD", "/there".to_string()).unwrap();
+        assert_eq!(expand("~ ~/a ~+ ~-/b", shell).unwrap(), ["/home/me", "/home/me/a", "/here", "/there/b"]);
+        assert_eq!(expand("'~' \"~\" \\~ a~ ~\"/a\" a:~", shell).unwrap(), ["~", "~", "~", "a~", "~/a", "a:~"]);
+        shell.vars.set("HOME", "/a b*".to_string()).unwrap();
+        assert_eq!(expand("~", shell).unwrap(), ["/a b*"]);
+    }
+
+    #[test]
+    fn looks_up_other_users(){
+        let shell = &mut test_shell();
+        let root = user_home(Some("root")).unwrap();
+        assert_eq!(expand("~root ~root/x", shell).unwrap(), [root.clone(), format!("{}/x", root)]);
+        assert_eq!(expand("~no_such_user_here/x", shell).unwrap(), ["~no_such_user_here/x"]);
+        shell.vars.unset("HOME").unwrap();
+        assert_eq!(expand("~", shell).unwrap(), [user_home(None).unwrap()]);
+    }
+
+    #[test]
+    fn expands_tildes_after_colons_in_assignments(){
+        let shell = &mut test_shell();
+        shell.vars.set("HOME", "/h".to_string()).unwrap();
+        shell.vars.set("PWD", "/p".to_string()).unwrap();
+        assert_eq!(assignment("~:~/b:x~:~+", shell), "/h:/h/b:x~:/p");
+        assert_eq!(assignment("x=~", shell), "x=~");
+        assert_eq!(assignment("'~':~", shell), "~:/h");
+        assert_eq!(expand("~:~", shell).unwrap(), ["~:~"]);
+    }
+
+    #[test]
+    fn substitutes_defaults_and_alternatives(){
+        let shell = &mut test_shell();
+        assert_eq!(expand("${v:-d} ${e:-d} ${u:-d} ${u-d}", shell).unwrap(), ["hello", "d", "d", "d"]);
+        assert_eq!(expand("${e-d}", shell).unwrap(), Vec::<String>::new());
+        assert_eq!(expand("\"${e-d}\"", shell).unwrap(), [""]);
+        assert_eq!(expand("${v:+a} ${e:+b} ${e+c} ${u+d} ${u:+e}", shell).unwrap(), ["a", "c"]);
+        assert_eq!(expand("${u:-a b} \"${u:-a b}\"", shell).unwrap(), ["a", "b", "a b"]);
+    }
+
+    #[test]
+    fn assigns_defaults(){
+        let shell = &mut test_shell();
+        assert_eq!(expand("${u:=d}", shell).unwrap(), ["d"]);
+        assert_eq!(shell.vars.get_scalar("u").as_deref(), Some("d"));
+        assert_eq!(expand("${e=d}", shell).unwrap(), Vec::<String>::new());
+        assert_eq!(shell.vars.get_scalar("e").as_deref(), Some(""));
+        assert_eq!(expand("${e:=d}", shell).unwrap(), ["d"]);
+        assert_eq!(shell.vars.get_scalar("e").as_deref(), Some("d"));
+        assert_eq!(expand("${1:=d}", shell), Err("$1: cannot assign in this way".to_string()));
+    }
+
+    #[test]
+    fn reports_missing_parameters(){
+        let shell = &mut test_shell();
+        shell.interactive = true;
+        assert_eq!(expand("${e?} ${v:?}", shell).unwrap(), ["hello"]);
+        assert_eq!(expand("${u:?}", shell), Err("u: parameter null or not set".to_string()));
+        assert_eq!(expand("${e:?is empty}", shell), Err("e: is empty".to_string()));
+        assert!(!shell.exit_requested);
+        shell.interactive = false;
+        assert!(expand("${u?}", shell).is_err());
+        assert!(shell.exit_requested);
+    }
+
+    #[test]
+    fn reports_arithmetic_errors(){
+        let shell = &mut test_shell();
+        shell.interactive = true;
+        assert_eq!(expand("$((1/0))", shell), Err("1/0: division by 0 (error token is \"0\")".to_string()));
+        assert!(!shell.exit_requested);
+        shell.interactive = false;
+        assert!(expand("x $((1 +))", shell).is_err());
+        assert!(shell.exit_requested);
+    }
+
+    #[test]
+    fn expands_special_parameters(){
+        let shell = &mut test_shell();
+        shell.positional_params = (1..=11).map(|n| format!("p{}", n)).collect();
+        shell.script_name = "script".to_string();
+        shell.last_status = 3;
+        assert_eq!(expand("$# $0 $1 ${10} $10 ${11} ${12}x $?", shell).unwrap(), ["11", "script", "p1", "p10", "p10", "p11", "x", "3"]);
+        assert_eq!(expand("$$", shell).unwrap(), [shell.pid.to_string()]);
+        assert_eq!(expand("[$!]", shell).unwrap(), ["[]"]);
+        shell.last_background_pid = Some(42);
+        assert_eq!(expand("$!", shell).unwrap(), ["42"]);
+        assert_eq!(expand("[$-]", shell).unwrap(), ["[]"]);
+        shell.options.errexit = true;
+        shell.options.nounset = true;
+        shell.options.xtrace = true;
+        assert_eq!(expand("$-", shell).unwrap(), ["eux"]);
+        shell.positional_params.truncate(2);
+        assert_eq!(expand("\"$@\" \"$*\" $#", shell).unwrap(), ["p1", "p2", "p1 p2", "2"]);
+    }
+
+    #[test]
+    fn counts_characters(){
+        let shell = &mut test_shell();
+        shell.vars.set("m", "héllo".to_string()).unwrap();
+        shell.positional_params = vec!["a".to_string(), "b".to_string()];
+        assert_eq!(expand("${#v} ${#m} ${#e} ${#u} ${#@}", shell).unwrap(), ["5", "5", "0", "0", "2"]);
+    }
+
+    #[test]
+    fn removes_prefixes_and_suffixes(){
+        assert_eq!(remove_prefix("a/b/c", "*/", false, false), "b/c");
+        assert_eq!(remove_prefix("a/b/c", "*/", true, false), "c");
+        assert_eq!(remove_prefix("a/b/c", "x", true, false), "a/b/c");
+        assert_eq!(remove_suffix("a.tar.gz", ".*", false, false), "a.tar");
+        assert_eq!(remove_suffix("a.tar.gz", ".*", true, false), "a");
+        assert_eq!(remove_suffix("héé", "é", false, false), "hé");
+        let shell = &mut test_shell();
+        shell.vars.set("p", "/usr/local/bin".to_string()).unwrap();
+        assert_eq!(expand("${p##*/} ${p%/*} ${p#\"/usr\"}", shell).unwrap(), ["bin", "/usr/local", "/local/bin"]);
+    }
+
+    #[test]
+    fn replaces_matches(){
+        assert_eq!(replace("aXbXc", "X", "-", ReplaceMode::First, false), "a-bXc");
+        assert_eq!(replace("aXbXc", "X", "-", ReplaceMode::All, false), "a-b-c");
+        assert_eq!(replace("aXbXc", "a", "-", ReplaceMode::Prefix, false), "-XbXc");
+        assert_eq!(replace("aXbXc", "X", "-", ReplaceMode::Prefix, false), "aXbXc");
+        assert_eq!(replace("aXbXc", "c", "-", ReplaceMode::Suffix, false), "aXbX-");
+        assert_eq!(replace("aXbXc", "X*", "-", ReplaceMode::First, false), "a-");
+        assert_eq!(replace("abc", "", "-", ReplaceMode::All, false), "abc");
+        let shell = &mut test_shell();
+        assert_eq!(expand("${v/l/L} ${v//l/L} ${v/#h/H} ${v/%o/O} ${v//l}", shell).unwrap(), ["heLlo", "heLLo", "Hello", "hellO", "heo"]);
+    }
+
+    #[test]
+    fn takes_substrings(){
+        assert_eq!(substring_range(5, 1, Some(2)), Ok((1, 3)));
+        assert_eq!(substring_range(5, -2, None), Ok((3, 5)));
+        assert_eq!(substring_range(5, 1, Some(-1)), Ok((1, 4)));
+        assert_eq!(substring_range(5, 2, Some(9)), Ok((2, 5)));
+        assert_eq!(substring_range(5, -7, None), Ok((0, 0)));
+        assert_eq!(substring_range(5, 9, None), Ok((0, 0)));
+        assert_eq!(substring_range(5, 3, Some(-3)), Err("-3: substring expression < 0".to_string()));
+        let shell = &mut test_shell();
+        shell.positional_params = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        assert_eq!(expand("${v:1:3} ${v: -2} ${v:(-4):2} ${v:1:-1} ${v:-2}", shell).unwrap(), ["ell", "lo", "el", "ell", "hello"]);
+        assert_eq!(expand("${@:2} ${@: -1}", shell).unwrap(), ["b", "c", "c"]);
+    }
+
+    #[test]
+    fn splits_on_ifs_whitespace(){
+        let shell = &mut test_shell();
+        shell.vars.set("w", "  a \t b\n ".to_string()).unwrap();
+        assert_eq!(expand("$w", shell).unwrap(), ["a", "b"]);
+        assert_eq!(expand("\"$w\"", shell).unwrap(), ["  a \t b\n "]);
+        assert_eq!(expand("x$w'y'", shell).unwrap(), ["x", "a", "b", "y"]);
+        assert_eq!(expand("$e", shell).unwrap(), Vec::<String>::new());
+        shell.vars.set("IFS", String::new()).unwrap();
+        assert_eq!(expand("$w", shell).unwrap(), ["  a \t b\n "]);
+    }
+
+    #[test]
+    fn splits_on_other_ifs_characters(){
+        let shell = &mut test_shell();
+        shell.vars.set("IFS", ":".to_string()).unwrap();
+        shell.vars.set("w", "a::b:".to_string()).unwrap();
+        assert_eq!(expand("$w", shell).unwrap(), ["a", "", "b"]);
+        shell.vars.set("IFS", " :".to_string()).unwrap();
+        shell.vars.set("w", " a : b  c:".to_string()).unwrap();
+        assert_eq!(expand("$w", shell).unwrap(), ["a", "b", "c"]);
+        assert_eq!(expand("a:b", shell).unwrap(), ["a:b"]);
+    }
+
+    #[test]
+    fn joins_positional_parameters(){
+        let shell = &mut test_shell();
+        shell.positional_params = vec!["a b".to_string(), "c".to_string()];
+        assert_eq!(expand("\"$@\"", shell).unwrap(), ["a b", "c"]);
+        assert_eq!(expand("x\"$@\"y", shell).unwrap(), ["xa b", "cy"]);
+        assert_eq!(expand("$@ $*", shell).unwrap(), ["a", "b", "c", "a", "b", "c"]);
+        assert_eq!(expand("\"$*\"", shell).unwrap(), ["a b c"]);
+        shell.vars.set("IFS", "-".to_string()).unwrap();
+        assert_eq!(expand("\"$*\"", shell).unwrap(), ["a b-c"]);
+        shell.vars.set("IFS", String::new()).unwrap();
+        assert_eq!(expand("\"$*\"", shell).unwrap(), ["a bc"]);
+        shell.positional_params.clear();
+        assert_eq!(expand("\"$@\"", shell).unwrap(), Vec::<String>::new());
+        assert_eq!(expand("\"$*\"", shell).unwrap(), [""]);
+    }
+}
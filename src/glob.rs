@@ -0,0 +1,514 @@
+use std::fs;
+use std::path::Path;
+
+/// Options from `shopt` that change how patterns are matched and expanded.
+#[derive(Debug, Clone, Default)]
+pub struct GlobOptions{
+    /// Patterns that match no file expand to nothing instead of themselves.
+    pub nullglob: bool,
+    /// Patterns that match no file are an error.
+    pub failglob: bool,
+    /// `*`, `?` and `[...]` also match names starting with a dot.
+    pub dotglob: bool,
+    /// `**` as a whole path component matches any number of directories.
+    pub globstar: bool,
+    /// Enables `?(...)`, `*(...)`, `+(...)`, `@(...)` and `!(...)`.
+    pub extglob: bool
+}
+
+impl GlobOptions{
+    /// Option names in the order `shopt` lists them.
+    pub const NAMES: [&'static str; 5] = ["dotglob", "extglob", "failglob", "globstar", "nullglob"];
+
+    pub fn option_mut(&mut self, name: &str)-> Option<&mut bool>{
+        match name {
+            "dotglob" => Some(&mut self.dotglob),
+            "extglob" => Some(&mut self.extglob),
+            "failglob" => Some(&mut self.failglob),
+            "globstar" => Some(&mut self.globstar),
+            "nullglob" => Some(&mut self.nullglob),
+            _ => None,
+        }
+    }
+}
+
+/// One element of a parsed pattern.
+#[derive(Debug, Clone)]
+enum PatternItem{
+    /// A character that matches itself.
+    Char(char),
+    /// `?`: any single character.
+    AnyChar,
+    /// `*`: any string, including the empty one.
+    AnyString,
+    /// `[...]`: one character from a set, or not from it when `negated`.
+    Class { negated: bool, items: Vec<ClassItem> },
+    /// An extended pattern such as `@(a|b)`; `kind` is the character
+    /// before the parenthesis.
+    Extended { kind: char, alternatives: Vec<Vec<PatternItem>> }
+}
+
+#[derive(Debug, Clone)]
+enum ClassItem{
+    Char(char),
+    Range(char, char),
+    /// A named class such as `[:alpha:]`.
+    Named(String)
+}
+
+/// Whether `text` matches the shell pattern `pattern` as a whole. `*`, `?`
+/// and bracket expressions (with `!` or `^` negation, ranges and classes
+/// like `[:digit:]`) are special; a backslash makes the next character
+/// match itself.
+///
+/// # Arguments
+/// * `pattern` - The pattern, with quoted characters escaped by a backslash
+/// * `text` - The string to match against
+/// * `extglob` - Whether extended patterns such as `+(a|b)` are recognised
+///
+/// # Returns
+/// * `bool` - True if the whole of `text` matches
+pub fn pattern_matches(pattern: &str, text: &str, extglob: bool)-> bool{
+    let chars: Vec<char> = pattern.chars().collect();
+    let items = parse_pattern(&chars, extglob);
+    let text: Vec<char> = text.chars().collect();
+    if items.iter().any(|item| matches!(item, PatternItem::Extended { .. })) {
+        matches_recursive(&items, &text)
+    }else{
+        matches_items(&items, &text)
+    }
+}
+
+/// Whether `pattern` contains any unescaped special characters, i.e.
+/// whether matching it differs from comparing strings.
+pub fn has_glob_chars(pattern: &str, extglob: bool)-> bool{
+    let chars: Vec<char> = pattern.chars().collect();
+    parse_pattern(&chars, extglob).iter().any(|item| !matches!(item, PatternItem::Char(_)))
+}
+
+/// Expands a pattern into the sorted paths of the existing files it matches.
+/// Each `/`-separated component is matched against the entries of the
+/// directories matched so far. Names starting with a dot are only matched
+/// by a pattern that starts with a dot, unless `dotglob` is set.
+///
+/// # Arguments
+/// * `pattern` - The pattern, with quoted characters escaped by a backslash
+/// * `options` - The `shopt` settings in effect
+///
+/// # Returns
+/// * `Vec<String>` - The matching paths; empty when nothing matches
+pub fn expand_pathname(pattern: &str, options: &GlobOptions)-> Vec<String>{
+    let components: Vec<&str> = pattern.split('/').collect();
+    let mut paths = vec![String::new()];
+    let mut rest = &components[..];
+    if pattern.starts_with('/') {
+        paths = vec!["/".to_string()];
+        rest = &components[1..];
+    }
+    for (i, component) in rest.iter().enumerate() {
+        let last = i == rest.len() - 1;
+        let mut next = Vec::new();
+        for path in &paths {
+            if component.is_empty() {
+                if is_directory(path) {
+                    next.push(format!("{}/", path.trim_end_matches('/')));
+                }
+            }else if *component == "**" && options.globstar {
+                if !last {
+                    next.push(path.clone());
+                }else if !path.is_empty() {
+                    next.push(format!("{}/", path.trim_end_matches('/')));
+                }
+                walk_directory(path, options.dotglob, last, &mut next);
+            }else if !has_glob_chars(component, options.extglob) {
+                next.push(join(path, &unescape(component)));
+            }else{
+                let Ok(entries) = fs::read_dir(if path.is_empty() { "." } else { path }) else {
+                    continue;
+                };
+                let show_hidden = options.dotglob || component.starts_with('.') || component.starts_with("\\.");
+                for entry in entries.filter_map(Result::ok) {
+                    let name = entry.file_name().to_string_lossy().to_string();
+                    if name.starts_with('.') && !show_hidden {
+                        continue;
+                    }
+                    if pattern_matches(component, &name, options.extglob) {
+                        next.push(join(path, &name));
+                    }
+                }
+            }
+        }
+        paths = next;
+        if !last {
+            paths.retain(|path| is_directory(path));
+        }
+    }
+    paths.retain(|path| !path.is_empty() && fs::symlink_metadata(path).is_ok());
+    paths.sort();
+    paths.dedup();
+    paths
+}
+
+/// Collects every entry below `dir` (only directories unless `files`), for `**`.
+fn walk_directory(dir: &str, dotglob: bool, files: bool, found: &mut Vec<String>){
+    let Ok(entries) = fs::read_dir(if dir.is_empty() { "." } else { dir }) else {
+        return;
+    };
+    for entry in entries.filter_map(Result::ok) {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if name.starts_with('.') && !dotglob {
+            continue;
+        }
+        let path = join(dir, &name);
+        let directory = entry.file_type().is_ok_and(|t| t.is_dir());
+        if directory || files {
+            found.push(path.clone());
+        }
+        if directory {
+            walk_directory(&path, dotglob, files, found);
+        }
+    }
+}
+
+fn is_directory(path: &str)-> bool{
+    path.is_empty() || Path::new(path).is_dir()
+}
+
+fn join(dir: &str, name: &str)-> String{
+    if dir.is_empty() {
+        name.to_string()
+    }else if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    }else{
+        format!("{}/{}", dir, name)
+    }
+}
+
+/// Removes the backslashes that escape characters of a pattern.
+pub fn unescape(pattern: &str)-> String{
+    let mut text = String::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            if let Some(next) = chars.next() {
+                text.push(next);
+                continue;
+            }
+        }
+        text.push(c);
+    }
+    text
+}
+
+fn parse_pattern(chars: &[char], extglob: bool)-> Vec<PatternItem>{
+    let mut items = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        if extglob && matches!(chars[i], '?' | '*' | '+' | '@' | '!') && chars.get(i + 1) == Some(&'(') {
+            if let Some(end) = find_closing_paren(chars, i + 2) {
+                let alternatives = split_alternatives(&chars[i + 2..end]).into_iter()
+                    .map(|alternative| parse_pattern(alternative, extglob))
+                    .collect();
+                items.push(PatternItem::Extended { kind: chars[i], alternatives });
+                i = end + 1;
+                continue;
+            }
+        }
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                items.push(PatternItem::Char(chars[i + 1]));
+                i += 2;
+                continue;
+            },
+            '*' => {
+                if !matches!(items.last(), Some(PatternItem::AnyString)) {
+                    items.push(PatternItem::AnyString);
+                }
+            },
+            '?' => items.push(PatternItem::AnyChar),
+            '[' => {
+                if let Some((item, end)) = parse_class(chars, i + 1) {
+                    items.push(item);
+                    i = end;
+                    continue;
+                }
+                items.push(PatternItem::Char('['));
+            },
+            c => items.push(PatternItem::Char(c)),
+        }
+        i += 1;
+    }
+    items
+}
+
+/// Finds the `)` closing an extended pattern whose contents start at `start`.
+fn find_closing_paren(chars: &[char], start: usize)-> Option<usize>{
+    let mut depth = 0;
+    let mut i = start;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '(' => depth += 1,
+            ')' if depth == 0 => return Some(i),
+            ')' => depth -= 1,
+            _ => {},
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Splits the contents of an extended pattern on its top-level `|`s.
+fn split_alternatives(chars: &[char])-> Vec<&[char]>{
+    let mut alternatives = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            '|' if depth == 0 => {
+                alternatives.push(&chars[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+        i += 1;
+    }
+    alternatives.push(&chars[start..]);
+    alternatives
+}
+
+/// Parses a bracket expression whose contents start at `start`, returning
+/// it and the index just past its closing `]`, or `None` if it is not closed.
+fn parse_class(chars: &[char], start: usize)-> Option<(PatternItem, usize)>{
+    let mut i = start;
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let mut items = Vec::new();
+    let first = i;
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && i > first {
+            return Some((PatternItem::Class { negated, items }, i + 1));
+        }
+        if c == '[' && chars.get(i + 1) == Some(&':') {
+            let rest: String = chars[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                items.push(ClassItem::Named(rest[..end].to_string()));
+                i += 2 + rest[..end].chars().count() + 2;
+                continue;
+            }
+        }
+        let c = if c == '\\' && i + 1 < chars.len() {
+            i += 1;
+            chars[i]
+        }else{
+            c
+        };
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&end| end != ']') {
+            let mut end = chars[i + 2];
+            i += 3;
+            if end == '\\' && i < chars.len() {
+                end = chars[i];
+                i += 1;
+            }
+            items.push(ClassItem::Range(c, end));
+            continue;
+        }
+        items.push(ClassItem::Char(c));
+        i += 1;
+    }
+}
+
+/// Matches with backtracking to the most recent `*`, which is enough since
+/// a later `*` can always absorb what an earlier one would have.
+fn matches_items(items: &[PatternItem], text: &[char])-> bool{
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        match items.get(p) {
+            Some(PatternItem::AnyString) => {
+                star = Some((p, t));
+                p += 1;
+                continue;
+            },
+            Some(item) if item_matches(item, text[t]) => {
+                p += 1;
+                t += 1;
+                continue;
+            },
+            _ => {},
+        }
+        match star {
+            Some((star_p, star_t)) => {
+                p = star_p + 1;
+                t = star_t + 1;
+                star = Some((star_p, star_t + 1));
+            },
+            None => return false,
+        }
+    }
+    items[p..].iter().all(|item| matches!(item, PatternItem::AnyString))
+}
+
+/// Matches by trying every way of dividing the text between the items,
+/// which extended patterns need since they can match strings of any length.
+fn matches_recursive(items: &[PatternItem], text: &[char])-> bool{
+    let Some((first, rest)) = items.split_first() else {
+        return text.is_empty();
+    };
+    match first {
+        PatternItem::AnyString => (0..=text.len()).any(|i| matches_recursive(rest, &text[i..])),
+        PatternItem::Extended { kind, alternatives } => (0..=text.len()).any(|i| {
+            let matched = match kind {
+                '@' => matches_any(alternatives, &text[..i]),
+                '?' => i == 0 || matches_any(alternatives, &text[..i]),
+                '*' => i == 0 || matches_repeated(alternatives, &text[..i]),
+                '+' => matches_repeated(alternatives, &text[..i]),
+                _ => !matches_any(alternatives, &text[..i]),
+            };
+            matched && matches_recursive(rest, &text[i..])
+        }),
+        item => !text.is_empty() && item_matches(item, text[0]) && matches_recursive(rest, &text[1..]),
+    }
+}
+
+fn matches_any(alternatives: &[Vec<PatternItem>], text: &[char])-> bool{
+    alternatives.iter().any(|alternative| matches_recursive(alternative, text))
+}
+
+/// Whether `text` is one or more matches of the alternatives in a row.
+fn matches_repeated(alternatives: &[Vec<PatternItem>], text: &[char])-> bool{
+    (1..=text.len()).any(|i| {
+        matches_any(alternatives, &text[..i]) && (i == text.len() || matches_repeated(alternatives, &text[i..]))
+    })
+}
+
+fn item_matches(item: &PatternItem, c: char)-> bool{
+    match item {
+        PatternItem::Char(expected) => *expected == c,
+        PatternItem::AnyChar => true,
+        PatternItem::AnyString | PatternItem::Extended { .. } => true,
+        PatternItem::Class { negated, items } => {
+            let found = items.iter().any(|class_item| match class_item {
+                ClassItem::Char(expected) => *expected == c,
+                ClassItem::Range(low, high) => *low <= c && c <= *high,
+                ClassItem::Named(name) => class_contains(name, c),
+            });
+            found != *negated
+        },
+    }
+}
+
+fn class_contains(name: &str, c: char)-> bool{
+    match name {
+        "alpha" => c.is_alphabetic(),
+        "digit" => c.is_ascii_digit(),
+        "alnum" => c.is_alphanumeric(),
+        "upper" => c.is_uppercase(),
+        "lower" => c.is_lowercase(),
+        "space" => c.is_whitespace(),
+        "blank" => c == ' ' || c == '\t',
+        "punct" => c.is_ascii_punctuation(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        "cntrl" => c.is_control(),
+        "print" => !c.is_control(),
+        "graph" => !c.is_control() && !c.is_whitespace(),
+        "word" => c.is_alphanumeric() || c == '_',
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, text: &str)-> bool{
+        pattern_matches(pattern, text, false)
+    }
+
+    fn extmatches(pattern: &str, text: &str)-> bool{
+        pattern_matches(pattern, text, true)
+    }
+
+    #[test]
+    fn matches_wildcards(){
+        assert!(matches("*.txt", "a.txt"));
+        assert!(matches("*", ""));
+        assert!(!matches("*.txt", "a.txt.bak"));
+        assert!(matches("a?c", "abc"));
+        assert!(!matches("a?c", "ac"));
+        assert!(matches("*a*b*", "xaybz"));
+    }
+
+    #[test]
+    fn matches_bracket_expressions(){
+        assert!(matches("[abc]", "b"));
+        assert!(!matches("[abc]", "d"));
+        assert!(matches("[a-c]x", "bx"));
+        assert!(matches("[!a-c]", "d"));
+        assert!(matches("[^a-c]", "d"));
+        assert!(!matches("[!a-c]", "a"));
+        assert!(matches("[]]", "]"));
+        assert!(matches("[a-]", "-"));
+        assert!(matches("[[:digit:]][[:alpha:]]", "1z"));
+        assert!(!matches("[[:upper:]]", "a"));
+        assert!(matches("[", "["));
+    }
+
+    #[test]
+    fn escaped_characters_match_themselves(){
+        assert!(matches("\\*", "*"));
+        assert!(!matches("\\*", "a"));
+        assert!(matches("a\\?", "a?"));
+        assert!(!has_glob_chars("a\\*b", false));
+        assert!(has_glob_chars("a*b", false));
+        assert_eq!(unescape("a\\*b\\\\"), "a*b\\");
+    }
+
+    #[test]
+    fn matches_extended_patterns(){
+        assert!(extmatches("@(a|bc)", "bc"));
+        assert!(!extmatches("@(a|bc)", "abc"));
+        assert!(extmatches("?(x)y", "y"));
+        assert!(extmatches("?(x)y", "xy"));
+        assert!(extmatches("*(ab)", "ababab"));
+        assert!(extmatches("+(ab)", "ab"));
+        assert!(!extmatches("+(ab)", ""));
+        assert!(extmatches("!(*.c)", "a.h"));
+        assert!(!extmatches("!(*.c)", "a.c"));
+        assert!(extmatches("*.@(jpg|png)", "x.png"));
+        assert!(!matches("@(a|b)", "a"));
+        assert!(has_glob_chars("@(a)", true));
+        assert!(!has_glob_chars("@(a)", false));
+    }
+
+    #[test]
+    fn expands_pathnames(){
+        let dir = std::env::temp_dir().join(format!("rust-shell-glob-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub/deep")).unwrap();
+        for name in ["a.txt", "b.txt", ".hidden.txt", "c.rs", "sub/d.txt", "sub/deep/e.txt"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let base = dir.to_str().unwrap();
+        let names = |pattern: &str, options: &GlobOptions| -> Vec<String>{
+            expand_pathname(&format!("{}/{}", base, pattern), options).into_iter()
+                .map(|path| path[base.len() + 1..].to_string()).collect()
+        };
+        let mut options = GlobOptions::default();
+        assert_eq!(names("*.txt", &options), ["a.txt", "b.txt"]);
+        assert_eq!(names("*/*.txt", &options), ["sub/d.txt"]);
+        assert!(names("*.none", &options).is_empty());
+        options.dotglob = true;
+        assert_eq!(names("*.txt", &options), [".hidden.txt", "a.txt", "b.txt"]);
+        options.dotglob = false;
+        options.globstar = true;
+        assert_eq!(names("**/*.txt", &options), ["a.txt", "b.txt", "sub/d.txt", "sub/deep/e.txt"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,586 @@
+use std::ffi::CStr;
+use std::os::fd::RawFd;
+
+use crate::signals;
+
+/// What a process of a job was last seen doing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProcessState{
+    Running,
+    /// Stopped by the signal, e.g. `SIGTSTP` from Ctrl-Z.
+    Stopped(i32),
+    /// Exited with the status.
+    Exited(i32),
+    /// Killed by the signal.
+    Signaled(i32)
+}
+
+impl ProcessState{
+    /// Decodes a status reported by `waitpid`.
+    fn from_wait_status(status: i32)-> Self{
+        if libc::WIFSTOPPED(status) {
+            ProcessState::Stopped(libc::WSTOPSIG(status))
+        }else if libc::WIFSIGNALED(status) {
+            ProcessState::Signaled(libc::WTERMSIG(status))
+        }else if libc::WIFCONTINUED(status) {
+            ProcessState::Running
+        }else{
+            ProcessState::Exited(libc::WEXITSTATUS(status))
+        }
+    }
+
+    fn is_done(self)-> bool{
+        matches!(self, ProcessState::Exited(_) | ProcessState::Signaled(_))
+    }
+
+    /// The exit status as `$?` shows it: `128 + n` for signal n.
+    fn status(self)-> i32{
+        match self {
+            ProcessState::Running => 0,
+            ProcessState::Exited(status) => status,
+            ProcessState::Stopped(signal) | ProcessState::Signaled(signal) => 128 + signal,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Process{
+    pub pid: libc::pid_t,
+    pub state: ProcessState
+}
+
+/// A pipeline or background list the shell keeps track of.
+#[derive(Clone)]
+pub struct Job{
+    /// The number shown as `[n]` and used by `%n`.
+    pub id: usize,
+    pub pgid: libc::pid_t,
+    pub processes: Vec<Process>,
+    /// The source text of the job, without a trailing `&`.
+    pub command: String,
+    /// Terminal modes the job had when it stopped, given back by `fg`.
+    termios: Option<libc::termios>,
+    /// Whether the current state has been reported to the user.
+    notified: bool,
+    /// The shell process that started the job, the only one that can wait
+    /// for it; subshells see the job in `jobs` but leave it alone.
+    parent: libc::pid_t
+}
+
+impl Job{
+    pub fn is_done(&self)-> bool{
+        self.processes.iter().all(|process| process.state.is_done())
+    }
+
+    pub fn is_stopped(&self)-> bool{
+        !self.is_done() && self.processes.iter().all(|process| process.state != ProcessState::Running)
+    }
+
+    /// The status of the job, which is that of its last process.
+    pub fn status(&self)-> i32{
+        self.processes.last().map(|process| process.state.status()).unwrap_or(0)
+    }
+
+    /// The state as `jobs` shows it: `Running`, `Stopped`, `Done`, `Exit n`
+    /// or the description of the signal that killed it.
+    pub fn state_text(&self)-> String{
+        if self.is_stopped() {
+            return "Stopped".to_string();
+        }
+        if !self.is_done() {
+            return "Running".to_string();
+        }
+        match self.processes.last().map(|process| process.state) {
+            Some(ProcessState::Exited(0)) | None => "Done".to_string(),
+            Some(ProcessState::Signaled(signal)) => signal_description(signal),
+            Some(state) => format!("Exit {}", state.status()),
+        }
+    }
+
+    /// The command, followed by `&` while it runs in the background.
+    pub fn display_command(&self)-> String{
+        if self.is_stopped() || self.is_done() {
+            self.command.clone()
+        }else{
+            format!("{} &", self.command)
+        }
+    }
+}
+
+/// The job table, and the terminal that foreground jobs are given while job
+/// control is on.
+#[derive(Default)]
+pub struct Jobs{
+    jobs: Vec<Job>,
+    /// Job numbers from the least to the most recently started or stopped;
+    /// the last is the current job `%+` and the one before it `%-`.
+    order: Vec<usize>,
+    /// A descriptor of the controlling terminal while job control is on.
+    terminal: Option<RawFd>,
+    shell_pgid: libc::pid_t,
+    shell_termios: Option<libc::termios>,
+    /// The text of the pipeline running in the foreground, which names the
+    /// job if it is stopped.
+    pub foreground: String
+}
+
+impl Jobs{
+    /// Turns on job control for an interactive shell: the shell waits until
+    /// it is in the foreground, puts itself in its own process group and
+    /// takes the terminal.
+    ///
+    /// # Returns
+    /// * `bool` - Whether standard input is a terminal, so job control is on
+    pub fn enable(&mut self)-> bool{
+        if unsafe { libc::isatty(0) } != 1 {
+            return false;
+        }
+        unsafe {
+            loop {
+                let pgid = libc::getpgrp();
+                if libc::tcgetpgrp(0) == pgid {
+                    break;
+                }
+                libc::kill(-pgid, libc::SIGTTIN);
+            }
+            let pid = libc::getpid();
+            libc::setpgid(pid, pid);
+            libc::tcsetpgrp(0, pid);
+            let terminal = libc::fcntl(0, libc::F_DUPFD_CLOEXEC, 10);
+            if terminal == -1 {
+                return false;
+            }
+            self.terminal = Some(terminal);
+            self.shell_pgid = pid;
+            self.shell_termios = get_termios(terminal);
+        }
+        true
+    }
+
+    pub fn enabled(&self)-> bool{
+        self.terminal.is_some()
+    }
+
+    /// The terminal descriptor while job control is on, for a child that
+    /// has to take the terminal before it runs.
+    pub fn terminal(&self)-> Option<RawFd>{
+        self.terminal
+    }
+
+    /// Turns job control off in a forked child that carries on as a
+    /// subshell. The job table stays, so that `jobs | cat` still lists the
+    /// jobs of the shell.
+    pub fn disable(&mut self){
+        self.terminal = None;
+    }
+
+    /// Puts a freshly forked child in the process group `pgid` from the
+    /// parent's side, so that neither of them depends on the other having
+    /// done so first.
+    pub fn place(&self, pid: libc::pid_t, pgid: libc::pid_t){
+        if self.enabled() {
+            unsafe { libc::setpgid(pid, pgid) };
+        }
+    }
+
+    /// Adds a job and makes it the current job.
+    ///
+    /// # Returns
+    /// * `usize` - The job number
+    pub fn add(&mut self, pgid: libc::pid_t, pids: &[libc::pid_t], command: &str)-> usize{
+        let processes = pids.iter().map(|&pid| Process { pid, state: ProcessState::Running }).collect();
+        let parent = unsafe { libc::getpid() };
+        self.insert(Job { id: 0, pgid, processes, command: command.to_string(), termios: None, notified: true, parent })
+    }
+
+    fn insert(&mut self, mut job: Job)-> usize{
+        job.id = self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+        let id = job.id;
+        self.jobs.push(job);
+        self.order.push(id);
+        id
+    }
+
+    /// Waits for the processes of a foreground pipeline to finish or stop,
+    /// giving the terminal to their process group in the meantime. When one
+    /// of them stops the pipeline becomes a stopped job.
+    ///
+    /// # Arguments
+    /// * `pids` - The processes, in pipeline order
+    /// * `pgid` - Their process group
+    ///
+    /// # Returns
+    /// * `Vec<i32>` - The status of every process; `128 + n` for one stopped
+    ///   or killed by signal n
+    pub fn wait_foreground(&mut self, pids: &[libc::pid_t], pgid: libc::pid_t)-> Vec<i32>{
+        if let Some(terminal) = self.terminal {
+            unsafe { libc::tcsetpgrp(terminal, pgid) };
+        }
+        let processes = pids.iter().map(|&pid| Process { pid, state: self.wait_process(pid) }).collect();
+        let parent = unsafe { libc::getpid() };
+        let job = Job { id: 0, pgid, processes, command: self.foreground.clone(), termios: None, notified: true, parent };
+        self.finish_foreground(job)
+    }
+
+    /// Takes the terminal back after a foreground job and records the job if
+    /// it stopped. A job killed by a signal is reported, except for `SIGINT`,
+    /// which interrupts the shell's command line instead, and `SIGPIPE`.
+    fn finish_foreground(&mut self, mut job: Job)-> Vec<i32>{
+        let statuses = job.processes.iter().map(|process| process.state.status()).collect();
+        if job.processes.iter().any(|process| process.state == ProcessState::Signaled(libc::SIGINT)) {
+            signals::interrupt();
+        }else if let Some(ProcessState::Signaled(signal)) = job.processes.last().map(|process| process.state) {
+            if signal != libc::SIGPIPE {
+                eprintln!("{}", signal_description(signal));
+            }
+        }
+        let Some(terminal) = self.terminal else {
+            return statuses;
+        };
+        unsafe { libc::tcsetpgrp(terminal, self.shell_pgid) };
+        if job.is_stopped() {
+            job.termios = get_termios(terminal);
+            let id = self.insert(job);
+            if let Some(job) = self.jobs.iter().find(|job| job.id == id) {
+                eprintln!();
+                eprintln!("{}", self.format_job(job, false));
+            }
+        }
+        if let Some(termios) = &self.shell_termios {
+            unsafe { libc::tcsetattr(terminal, libc::TCSADRAIN, termios) };
+        }
+        statuses
+    }
+
+    /// Waits for a process to exit, or also to stop while job control is on.
+    fn wait_process(&self, pid: libc::pid_t)-> ProcessState{
+        let flags = if self.enabled() { libc::WUNTRACED } else { 0 };
+        let mut status = 0;
+        loop {
+            if unsafe { libc::waitpid(pid, &mut status, flags) } != -1 {
+                return ProcessState::from_wait_status(status);
+            }
+            if std::io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                return ProcessState::Exited(127);
+            }
+        }
+    }
+
+    /// Collects the state changes of background jobs without waiting.
+    pub fn update(&mut self){
+        let pid = unsafe { libc::getpid() };
+        for job in self.jobs.iter_mut().filter(|job| job.parent == pid) {
+            for process in &mut job.processes {
+                if process.state.is_done() {
+                    continue;
+                }
+                let mut status = 0;
+                let flags = libc::WNOHANG | libc::WUNTRACED | libc::WCONTINUED;
+                match unsafe { libc::waitpid(process.pid, &mut status, flags) } {
+                    0 => {},
+                    -1 => {
+                        process.state = ProcessState::Exited(127);
+                        job.notified = false;
+                    },
+                    _ => {
+                        process.state = ProcessState::from_wait_status(status);
+                        job.notified = false;
+                    },
+                }
+            }
+        }
+    }
+
+    /// Reports the jobs that finished or stopped since the last report, as
+    /// the interactive shell does before every prompt. Finished jobs are
+    /// removed from the table.
+    pub fn notify(&mut self){
+        self.update();
+        for job in &self.jobs {
+            if !job.notified && (job.is_done() || job.is_stopped()) {
+                eprintln!("{}", self.format_job(job, false));
+            }
+        }
+        self.mark_reported();
+    }
+
+    /// Marks every job's state as reported and removes the finished ones,
+    /// after they have been listed.
+    pub fn mark_reported(&mut self){
+        for job in &mut self.jobs {
+            job.notified = true;
+        }
+        let done: Vec<usize> = self.jobs.iter().filter(|job| job.is_done()).map(|job| job.id).collect();
+        for id in done {
+            self.remove(id);
+        }
+    }
+
+    /// Removes a job from the table, as `disown` does.
+    pub fn remove(&mut self, id: usize)-> Option<Job>{
+        self.order.retain(|&other| other != id);
+        let index = self.jobs.iter().position(|job| job.id == id)?;
+        Some(self.jobs.remove(index))
+    }
+
+    pub fn ids(&self)-> Vec<usize>{
+        self.jobs.iter().map(|job| job.id).collect()
+    }
+
+    pub fn get(&self, id: usize)-> Option<&Job>{
+        self.jobs.iter().find(|job| job.id == id)
+    }
+
+    /// Finds the job a job spec refers to: `%n`, `%+` or `%%` (the current
+    /// job), `%-` (the previous one), `%string` (a job whose command starts
+    /// with string) or `%?string` (one whose command contains it). No spec
+    /// means the current job.
+    ///
+    /// # Arguments
+    /// * `spec` - The job spec, if one was given
+    /// * `builtin` - The built-in asking, for error messages
+    ///
+    /// # Returns
+    /// * `Result<usize, String>` - The job number, or an error message
+    pub fn resolve(&self, spec: Option<&str>, builtin: &str)-> Result<usize, String>{
+        let no_such_job = || format!("{}: {}: no such job", builtin, spec.unwrap_or("current"));
+        let Some(spec) = spec else {
+            return self.order.last().copied().ok_or_else(no_such_job);
+        };
+        let Some(name) = spec.strip_prefix('%') else {
+            return match spec.parse::<libc::pid_t>() {
+                Ok(pid) => self.jobs.iter()
+                    .find(|job| job.pgid == pid || job.processes.iter().any(|process| process.pid == pid))
+                    .map(|job| job.id)
+                    .ok_or_else(no_such_job),
+                Err(_) => Err(no_such_job()),
+            };
+        };
+        let matches: Vec<usize> = match name {
+            "" | "+" | "%" => self.order.last().copied().into_iter().collect(),
+            "-" => self.order.iter().rev().nth(1).or(self.order.last()).copied().into_iter().collect(),
+            _ if name.parse::<usize>().is_ok() => {
+                let id = name.parse::<usize>().unwrap_or(0);
+                self.get(id).map(|job| job.id).into_iter().collect()
+            },
+            _ => match name.strip_prefix('?') {
+                Some(text) => self.jobs.iter().filter(|job| job.command.contains(text)).map(|job| job.id).collect(),
+                None => self.jobs.iter().filter(|job| job.command.starts_with(name)).map(|job| job.id).collect(),
+            },
+        };
+        match matches.as_slice() {
+            [id] => Ok(*id),
+            [] => Err(no_such_job()),
+            _ => Err(format!("{}: {}: ambiguous job spec", builtin, spec)),
+        }
+    }
+
+    /// Formats a job the way `jobs` lists it, with its process ID when
+    /// `with_pid`.
+    pub fn format_job(&self, job: &Job, with_pid: bool)-> String{
+        let mark = if self.order.last() == Some(&job.id) {
+            '+'
+        }else if self.order.iter().rev().nth(1) == Some(&job.id) {
+            '-'
+        }else{
+            ' '
+        };
+        if with_pid {
+            format!("[{}]{} {} {:<24}{}", job.id, mark, job.pgid, job.state_text(), job.display_command())
+        }else{
+            format!("[{}]{}  {:<24}{}", job.id, mark, job.state_text(), job.display_command())
+        }
+    }
+
+    /// Continues a job in the foreground and waits for it, as `fg` does.
+    ///
+    /// # Returns
+    /// * `i32` - The status of the job once it finishes or stops again
+    pub fn foreground(&mut self, id: usize)-> i32{
+        let Some(mut job) = self.remove(id) else {
+            return 1;
+        };
+        println!("{}", job.command);
+        if let (Some(terminal), Some(termios)) = (self.terminal, &job.termios) {
+            unsafe { libc::tcsetattr(terminal, libc::TCSADRAIN, termios) };
+        }
+        if let Some(terminal) = self.terminal {
+            unsafe { libc::tcsetpgrp(terminal, job.pgid) };
+        }
+        unsafe { libc::kill(-job.pgid, libc::SIGCONT) };
+        for process in &mut job.processes {
+            if !process.state.is_done() {
+                process.state = self.wait_process(process.pid);
+            }
+        }
+        job.notified = true;
+        let status = job.status();
+        self.finish_foreground(job);
+        status
+    }
+
+    /// Continues a stopped job in the background, as `bg` does.
+    pub fn background(&mut self, id: usize){
+        self.order.retain(|&other| other != id);
+        self.order.push(id);
+        let Some(job) = self.jobs.iter_mut().find(|job| job.id == id) else {
+            return;
+        };
+        for process in &mut job.processes {
+            if !process.state.is_done() {
+                process.state = ProcessState::Running;
+            }
+        }
+        unsafe { libc::kill(-job.pgid, libc::SIGCONT) };
+        println!("[{}]+ {}", job.id, job.display_command());
+    }
+
+    /// Waits for a job to finish, as `wait` does, and removes it.
+    ///
+    /// # Returns
+    /// * `i32` - The status of the job
+    pub fn wait_for(&mut self, id: usize)-> i32{
+        let Some(mut job) = self.remove(id) else {
+            return 127;
+        };
+        if job.parent != unsafe { libc::getpid() } {
+            return 127;
+        }
+        for process in &mut job.processes {
+            if !process.state.is_done() {
+                let mut status = 0;
+                while unsafe { libc::waitpid(process.pid, &mut status, 0) } == -1 {
+                    if std::io::Error::last_os_error().raw_os_error() != Some(libc::EINTR) {
+                        status = 127 << 8;
+                        break;
+                    }
+                }
+                process.state = ProcessState::from_wait_status(status);
+            }
+        }
+        job.status()
+    }
+}
+
+/// Prepares a forked child to run as (part of) a job: it joins the process
+/// group `pgid`, or leads a new one when `pgid` is 0, and takes the
+/// terminal if it runs in the foreground, which is skipped without a
+/// terminal, i.e. when job control is off. Then the signals the interactive
+/// shell handles get their default action back.
+///
+/// # Arguments
+/// * `terminal` - The terminal descriptor from `Jobs::terminal`
+/// * `pgid` - The process group to join, 0 for a new one
+/// * `foreground` - Whether the job runs in the foreground
+pub fn enter_process_group(terminal: Option<RawFd>, pgid: libc::pid_t, foreground: bool){
+    if let Some(terminal) = terminal {
+        unsafe {
+            libc::setpgid(0, pgid);
+            if foreground {
+                libc::tcsetpgrp(terminal, libc::getpgrp());
+            }
+        }
+    }
+    signals::restore_defaults();
+}
+
+fn get_termios(terminal: RawFd)-> Option<libc::termios>{
+    let mut termios = std::mem::MaybeUninit::<libc::termios>::uninit();
+    if unsafe { libc::tcgetattr(terminal, termios.as_mut_ptr()) } == -1 {
+        return None;
+    }
+    Some(unsafe { termios.assume_init() })
+}
+
+/// The description of a signal, such as `Terminated`.
+fn signal_description(signal: i32)-> String{
+    let description = unsafe { libc::strsignal(signal) };
+    if description.is_null() {
+        return format!("Signal {}", signal);
+    }
+    unsafe { CStr::from_ptr(description) }.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A table of three running jobs; the last one started is current.
+    fn test_jobs()-> Jobs{
+        let mut jobs = Jobs::default();
+        jobs.add(100, &[100], "sleep 10");
+        jobs.add(200, &[200, 201], "cat file | grep x");
+        jobs.add(300, &[300], "sleep 20");
+        jobs
+    }
+
+    #[test]
+    fn resolves_numbers_and_current_jobs(){
+        let jobs = test_jobs();
+        assert_eq!(jobs.resolve(Some("%1"), "fg"), Ok(1));
+        assert_eq!(jobs.resolve(Some("%3"), "fg"), Ok(3));
+        assert_eq!(jobs.resolve(None, "fg"), Ok(3));
+        assert_eq!(jobs.resolve(Some("%+"), "fg"), Ok(3));
+        assert_eq!(jobs.resolve(Some("%%"), "fg"), Ok(3));
+        assert_eq!(jobs.resolve(Some("%"), "fg"), Ok(3));
+        assert_eq!(jobs.resolve(Some("%-"), "fg"), Ok(2));
+        assert_eq!(jobs.resolve(Some("201"), "wait"), Ok(2));
+    }
+
+    #[test]
+    fn resolves_command_prefixes_and_substrings(){
+        let jobs = test_jobs();
+        assert_eq!(jobs.resolve(Some("%cat"), "fg"), Ok(2));
+        assert_eq!(jobs.resolve(Some("%?grep"), "fg"), Ok(2));
+        assert_eq!(jobs.resolve(Some("%sleep 2"), "fg"), Ok(3));
+        assert_eq!(jobs.resolve(Some("%sleep"), "fg"), Err("fg: %sleep: ambiguous job spec".to_string()));
+        assert_eq!(jobs.resolve(Some("%?ee"), "bg"), Err("bg: %?ee: ambiguous job spec".to_string()));
+    }
+
+    #[test]
+    fn reports_unknown_jobs(){
+        let mut jobs = test_jobs();
+        assert_eq!(jobs.resolve(Some("%4"), "fg"), Err("fg: %4: no such job".to_string()));
+        assert_eq!(jobs.resolve(Some("%vi"), "fg"), Err("fg: %vi: no such job".to_string()));
+        assert_eq!(jobs.resolve(Some("999"), "wait"), Err("wait: 999: no such job".to_string()));
+        assert_eq!(jobs.resolve(Some("x"), "wait"), Err("wait: x: no such job".to_string()));
+        for id in jobs.ids() {
+            jobs.remove(id);
+        }
+        assert_eq!(jobs.resolve(None, "fg"), Err("fg: current: no such job".to_string()));
+        assert_eq!(jobs.resolve(Some("%-"), "fg"), Err("fg: %-: no such job".to_string()));
+    }
+
+    #[test]
+    fn previous_job_falls_back_to_the_current_one(){
+        let mut jobs = Jobs::default();
+        jobs.add(100, &[100], "sleep 10");
+        assert_eq!(jobs.resolve(Some("%-"), "fg"), Ok(1));
+    }
+
+    #[test]
+    fn lists_jobs_like_bash(){
+        let mut jobs = test_jobs();
+        jobs.jobs[0].processes[0].state = ProcessState::Stopped(libc::SIGTSTP);
+        jobs.jobs[1].processes[0].state = ProcessState::Exited(0);
+        jobs.jobs[1].processes[1].state = ProcessState::Exited(1);
+        let listing: Vec<String> = jobs.jobs.iter().map(|job| jobs.format_job(job, false)).collect();
+        assert_eq!(listing, [
+            "[1]   Stopped                 sleep 10",
+            "[2]-  Exit 1                  cat file | grep x",
+            "[3]+  Running                 sleep 20 &",
+        ]);
+        assert_eq!(jobs.format_job(&jobs.jobs[2], true), "[3]+ 300 Running                 sleep 20 &");
+    }
+
+    #[test]
+    fn describes_how_jobs_ended(){
+        let mut jobs = test_jobs();
+        jobs.jobs[0].processes[0].state = ProcessState::Exited(0);
+        jobs.jobs[2].processes[0].state = ProcessState::Signaled(libc::SIGTERM);
+        assert_eq!(jobs.jobs[0].state_text(), "Done");
+        assert_eq!(jobs.jobs[2].state_text(), "Terminated");
+        assert_eq!(jobs.jobs[2].status(), 128 + libc::SIGTERM);
+        assert_eq!(jobs.format_job(&jobs.jobs[0], false), "[1]   Done                    sleep 10");
+    }
+}
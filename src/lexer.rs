@@ -0,0 +1,966 @@
+use std::ops::Range;
+
+use crate::ast::{ParamExpansion, Program, ParamOp, ReplaceMode, Word, WordPart};
+use crate::parser::parse_program;
+
+/// A token of the shell grammar.
+#[derive(Debug, Clone)]
+pub enum Token{
+    Word(Word),
+    /// The digits of a redirection such as `2>`, when they directly precede
+    /// the operator.
+    IoNumber(i32),
+    Operator(Operator),
+    /// A `((expression))` command, read as a whole since its contents follow
+    /// the rules of arithmetic rather than of the shell.
+    Arithmetic(Word),
+    Newline
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator{
+    Pipe,
+    AndIf,
+    OrIf,
+    Semi,
+    Less,
+    DLess,
+    DLessDash,
+    TLess,
+    Great,
+    DGreat,
+    /// `>|`, which overwrites a file even with `set -C`.
+    Clobber,
+    LessAnd,
+    GreatAnd,
+    LessGreat,
+    AndGreat,
+    AndDGreat,
+    /// `&`, which runs the and-or list before it in the background.
+    Amp,
+    LParen,
+    RParen,
+    /// `;;`, which ends a `case` item.
+    DSemi,
+    /// `;&`, which ends a `case` item and falls through to the next body.
+    SemiAnd,
+    /// `;;&`, which ends a `case` item and goes on testing the next patterns.
+    DSemiAnd
+}
+
+/// Reasons a command line could not be tokenized or parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError{
+    /// The input ended in the middle of a construct, e.g. an open quote.
+    Incomplete(String),
+    /// The input can never become valid by appending more text.
+    Syntax(String)
+}
+
+impl std::fmt::Display for ParseError{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>)-> std::fmt::Result{
+        match self {
+            ParseError::Incomplete(msg) | ParseError::Syntax(msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+/// Splits a command line into words and operators. Quotes and backslashes
+/// are kept in the word structure so later stages know what was quoted.
+/// Here-document bodies are read after the newline that ends the line
+/// holding their `<<` operator and take the place of the delimiter word.
+///
+/// # Arguments
+/// * `input` - The command line string to tokenize
+///
+/// # Returns
+/// * `Result<Vec<(Token, Range<usize>)>, ParseError>` - The tokens with the
+///   character range of the source each was read from, or why the input is invalid
+pub fn tokenize(input: &str)-> Result<Vec<(Token, Range<usize>)>, ParseError>{
+    let mut lexer = Lexer { chars: input.chars().collect(), pos: 0, pending_here_docs: Vec::new() };
+    let mut tokens = Vec::new();
+    let mut spans = Vec::new();
+    let mut start = 0;
+    while let Some(c) = lexer.peek() {
+        spans.resize(tokens.len(), start..lexer.pos);
+        start = lexer.pos;
+        match c {
+            ' ' | '\t' => { lexer.pos += 1; },
+            // A backslash-newline between words joins the lines; at the end
+            // of the input it asks for the next line.
+            '\\' if lexer.peek_at(1) == Some('\n') => {
+                lexer.pos += 2;
+                lexer.continue_line()?;
+            },
+            // A `#` that starts a word comments out the rest of the line.
+            '#' => {
+                while lexer.peek().is_some_and(|c| c != '\n') {
+                    lexer.pos += 1;
+                }
+            },
+            '\n' => {
+                lexer.pos += 1;
+                tokens.push(Token::Newline);
+                lexer.read_here_doc_bodies(&mut tokens)?;
+            },
+            '|' | '>' | '<' | ';' => {
+                let operator = lexer.read_operator();
+                tokens.push(Token::Operator(operator));
+                if matches!(operator, Operator::DLess | Operator::DLessDash) {
+                    lexer.read_here_doc_delimiter(&mut tokens, operator == Operator::DLessDash)?;
+                }
+            },
+            '&' => tokens.push(Token::Operator(lexer.read_operator())),
+            '(' | ')' => {
+                if c == '(' && lexer.peek_at(1) == Some('(') {
+                    if let Some(expression) = lexer.read_arithmetic()? {
+                        tokens.push(Token::Arithmetic(expression));
+                        continue;
+                    }
+                }
+                tokens.push(Token::Operator(lexer.read_operator()));
+            },
+            _ => {
+                let word = lexer.read_word()?;
+                let digits = word.as_literal().filter(|s| !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()));
+                match digits.and_then(|s| s.parse::<i32>().ok()) {
+                    Some(fd) if matches!(lexer.peek(), Some('>' | '<')) => tokens.push(Token::IoNumber(fd)),
+                    _ => tokens.push(Token::Word(word)),
+                }
+            }
+        }
+    }
+    if let Some(here_doc) = lexer.pending_here_docs.first() {
+        return Err(ParseError::Incomplete(format!("here-document delimited by end-of-file (wanted `{}')", here_doc.delimiter)));
+    }
+    spans.resize(tokens.len(), start..lexer.pos);
+    Ok(tokens.into_iter().zip(spans).collect())
+}
+
+struct Lexer{
+    chars: Vec<char>,
+    pos: usize,
+    /// Here-documents whose body has not been read yet, in source order.
+    pending_here_docs: Vec<PendingHereDoc>
+}
+
+struct PendingHereDoc{
+    /// Index of the placeholder token the body replaces.
+    token_index: usize,
+    delimiter: String,
+    /// A quoted delimiter disables expansion in the body.
+    quoted: bool,
+    /// `<<-` strips leading tabs from body lines and the delimiter line.
+    strip_tabs: bool
+}
+
+impl Lexer{
+    fn peek(&self)-> Option<char>{
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize)-> Option<char>{
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn next(&mut self)-> Option<char>{
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    fn read_operator(&mut self)-> Operator{
+        let c = self.next();
+        match c {
+            Some('>') if self.peek() == Some('>') => {
+                self.pos += 1;
+                Operator::DGreat
+            },
+            Some('>') if self.peek() == Some('&') => {
+                self.pos += 1;
+                Operator::GreatAnd
+            },
+            Some('>') if self.peek() == Some('|') => {
+                self.pos += 1;
+                Operator::Clobber
+            },
+            Some('>') => Operator::Great,
+            Some('|') if self.peek() == Some('|') => {
+                self.pos += 1;
+                Operator::OrIf
+            },
+            Some('&') if self.peek() == Some('>') => {
+                self.pos += 1;
+                if self.peek() == Some('>') {
+                    self.pos += 1;
+                    Operator::AndDGreat
+                }else{
+                    Operator::AndGreat
+                }
+            },
+            Some('&') if self.peek() == Some('&') => {
+                self.pos += 1;
+                Operator::AndIf
+            },
+            Some('&') => Operator::Amp,
+            Some(';') if self.peek() == Some(';') => {
+                self.pos += 1;
+                if self.peek() == Some('&') {
+                    self.pos += 1;
+                    Operator::DSemiAnd
+                }else{
+                    Operator::DSemi
+                }
+            },
+            Some(';') if self.peek() == Some('&') => {
+                self.pos += 1;
+                Operator::SemiAnd
+            },
+            Some(';') => Operator::Semi,
+            Some('(') => Operator::LParen,
+            Some(')') => Operator::RParen,
+            Some('<') if self.peek() == Some('<') => {
+                self.pos += 1;
+                match self.peek() {
+                    Some('<') => {
+                        self.pos += 1;
+                        Operator::TLess
+                    },
+                    Some('-') => {
+                        self.pos += 1;
+                        Operator::DLessDash
+                    },
+                    _ => Operator::DLess,
+                }
+            },
+            Some('<') if self.peek() == Some('&') => {
+                self.pos += 1;
+                Operator::LessAnd
+            },
+            Some('<') if self.peek() == Some('>') => {
+                self.pos += 1;
+                Operator::LessGreat
+            },
+            Some('<') => Operator::Less,
+            _ => Operator::Pipe,
+        }
+    }
+
+    /// Checks that a backslash-newline just read is followed by more input,
+    /// which it continues onto: at the end of the input the command is
+    /// incomplete.
+    fn continue_line(&self)-> Result<(), ParseError>{
+        match self.peek() {
+            Some(_) => Ok(()),
+            None => Err(ParseError::Incomplete("unexpected end of file after `\\'".to_string())),
+        }
+    }
+
+    fn read_word(&mut self)-> Result<Word, ParseError>{
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        while let Some(c) = self.peek() {
+            if is_metachar(c) {
+                break;
+            }
+            match c {
+                '\\' => {
+                    self.pos += 1;
+                    match self.next() {
+                        Some('\n') => self.continue_line()?,
+                        Some(escaped) => {
+                            flush_literal(&mut literal, &mut parts);
+                            parts.push(WordPart::Escaped(escaped));
+                        },
+                        None => return Err(ParseError::Incomplete("unexpected end of file after `\\'".to_string())),
+                    }
+                },
+                '\'' => {
+                    self.pos += 1;
+                    flush_literal(&mut literal, &mut parts);
+                    parts.push(WordPart::SingleQuoted(self.read_single_quoted()?));
+                },
+                '"' => {
+                    self.pos += 1;
+                    flush_literal(&mut literal, &mut parts);
+                    parts.push(WordPart::DoubleQuoted(self.read_double_quoted()?));
+                },
+                '`' => {
+                    self.pos += 1;
+                    flush_literal(&mut literal, &mut parts);
+                    parts.push(self.read_backquoted()?);
+                },
+                '$' => match self.read_dollar()? {
+                    Some(part) => {
+                        flush_literal(&mut literal, &mut parts);
+                        parts.push(part);
+                    },
+                    None => literal.push('$'),
+                },
+                '?' | '*' | '+' | '@' | '!' if self.peek_at(1) == Some('(') => {
+                    self.read_extended_pattern(&mut literal)?;
+                },
+                _ => {
+                    self.pos += 1;
+                    literal.push(c);
+                }
+            }
+        }
+        flush_literal(&mut literal, &mut parts);
+        Ok(Word { parts })
+    }
+
+    /// Reads an extended pattern such as `@(a|b)` into `literal`, so that
+    /// the `|`s and blanks inside it do not end the word.
+    fn read_extended_pattern(&mut self, literal: &mut String)-> Result<(), ParseError>{
+        let mut depth = 0;
+        loop {
+            let c = self.next().ok_or_else(|| ParseError::Incomplete("unexpected EOF while looking for matching `)'".to_string()))?;
+            literal.push(c);
+            match c {
+                '\\' => literal.extend(self.next()),
+                '(' => depth += 1,
+                ')' if depth == 1 => return Ok(()),
+                ')' => depth -= 1,
+                _ => {},
+            }
+        }
+    }
+
+    fn read_single_quoted(&mut self)-> Result<String, ParseError>{
+        let mut text = String::new();
+        loop {
+            match self.next() {
+                Some('\'') => return Ok(text),
+                Some(c) => text.push(c),
+                None => return Err(ParseError::Incomplete("unexpected EOF while looking for matching `''".to_string())),
+            }
+        }
+    }
+
+    fn read_double_quoted(&mut self)-> Result<Vec<WordPart>, ParseError>{
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        loop {
+            match self.peek() {
+                Some('"') => {
+                    self.pos += 1;
+                    flush_literal(&mut literal, &mut parts);
+                    return Ok(parts);
+                },
+                Some('\\') => {
+                    self.pos += 1;
+                    match self.next() {
+                        Some('\n') => self.continue_line()?,
+                        Some(next) if matches!(next, '"' | '\\' | '$' | '`') => literal.push(next),
+                        Some(next) => {
+                            literal.push('\\');
+                            literal.push(next);
+                        },
+                        None => return Err(ParseError::Incomplete("unexpected EOF while looking for matching `\"'".to_string())),
+                    }
+                },
+                Some('`') => {
+                    self.pos += 1;
+                    flush_literal(&mut literal, &mut parts);
+                    parts.push(self.read_backquoted()?);
+                },
+                Some('$') => match self.read_dollar()? {
+                    Some(part) => {
+                        flush_literal(&mut literal, &mut parts);
+                        parts.push(part);
+                    },
+                    None => literal.push('$'),
+                },
+                Some(c) => {
+                    self.pos += 1;
+                    literal.push(c);
+                },
+                None => return Err(ParseError::Incomplete("unexpected EOF while looking for matching `\"'".to_string())),
+            }
+        }
+    }
+
+    /// Reads the delimiter word after `<<` or `<<-` and leaves a placeholder
+    /// token for the body.
+    fn read_here_doc_delimiter(&mut self, tokens: &mut Vec<Token>, strip_tabs: bool)-> Result<(), ParseError>{
+        while matches!(self.peek(), Some(' ' | '\t')) {
+            self.pos += 1;
+        }
+        match self.peek() {
+            Some(c) if !is_metachar(c) => {},
+            Some('\n') | None => return Err(ParseError::Syntax("syntax error near unexpected token `newline'".to_string())),
+            Some(c) => return Err(ParseError::Syntax(format!("syntax error near unexpected token `{}'", c))),
+        }
+        let start = self.pos;
+        self.read_word()?;
+        let raw: String = self.chars[start..self.pos].iter().collect();
+        let quoted = raw.contains(['\'', '"', '\\']);
+        let delimiter = remove_quotes(&raw);
+        self.pending_here_docs.push(PendingHereDoc { token_index: tokens.len(), delimiter, quoted, strip_tabs });
+        tokens.push(Token::Word(Word::default()));
+        Ok(())
+    }
+
+    /// Reads the bodies of all pending here-documents, one after another,
+    /// from the lines following the current position.
+    fn read_here_doc_bodies(&mut self, tokens: &mut [Token])-> Result<(), ParseError>{
+        for here_doc in std::mem::take(&mut self.pending_here_docs) {
+            let mut body = String::new();
+            loop {
+                if self.peek().is_none() {
+                    return Err(ParseError::Incomplete(format!("here-document delimited by end-of-file (wanted `{}')", here_doc.delimiter)));
+                }
+                let mut line = String::new();
+                while let Some(c) = self.next() {
+                    if c == '\n' {
+                        break;
+                    }
+                    line.push(c);
+                }
+                if here_doc.strip_tabs {
+                    line = line.trim_start_matches('\t').to_string();
+                }
+                if line == here_doc.delimiter {
+                    break;
+                }
+                body.push_str(&line);
+                body.push('\n');
+            }
+            let part = if here_doc.quoted {
+                WordPart::SingleQuoted(body)
+            }else{
+                let mut body_lexer = Lexer { chars: body.chars().collect(), pos: 0, pending_here_docs: Vec::new() };
+                WordPart::DoubleQuoted(body_lexer.read_here_doc_text()?)
+            };
+            tokens[here_doc.token_index] = Token::Word(Word { parts: vec![part] });
+        }
+        Ok(())
+    }
+
+    /// Splits the body of a here-document with an unquoted delimiter into
+    /// literal text and expansions. Backslash only escapes `$`, `` ` ``,
+    /// `\\` and newline, as in double quotes, but `"` is an ordinary character.
+    fn read_here_doc_text(&mut self)-> Result<Vec<WordPart>, ParseError>{
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '\\' => {
+                    self.pos += 1;
+                    match self.next() {
+                        Some('\n') => {},
+                        Some(next) if matches!(next, '\\' | '$' | '`') => literal.push(next),
+                        Some(next) => {
+                            literal.push('\\');
+                            literal.push(next);
+                        },
+                        None => literal.push('\\'),
+                    }
+                },
+                '`' => {
+                    self.pos += 1;
+                    flush_literal(&mut literal, &mut parts);
+                    parts.push(self.read_backquoted()?);
+                },
+                '$' => match self.read_dollar()? {
+                    Some(part) => {
+                        flush_literal(&mut literal, &mut parts);
+                        parts.push(part);
+                    },
+                    None => literal.push('$'),
+                },
+                _ => {
+                    self.pos += 1;
+                    literal.push(c);
+                }
+            }
+        }
+        flush_literal(&mut literal, &mut parts);
+        Ok(parts)
+    }
+
+    /// Reads an expansion starting at a `$`. Returns `None`, consuming only
+    /// the `$`, when it does not start an expansion.
+    fn read_dollar(&mut self)-> Result<Option<WordPart>, ParseError>{
+        self.pos += 1;
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                Ok(Some(WordPart::Parameter(self.read_braced_parameter()?)))
+            },
+            Some('(') => {
+                if self.peek_at(1) == Some('(') {
+                    if let Some(expression) = self.read_arithmetic()? {
+                        return Ok(Some(WordPart::Arithmetic(expression)));
+                    }
+                }
+                self.pos += 1;
+                Ok(Some(self.read_command_substitution()?))
+            },
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    self.pos += 1;
+                }
+                let name = self.chars[start..self.pos].iter().collect();
+                Ok(Some(WordPart::Parameter(ParamExpansion { name, index: None, op: ParamOp::Value })))
+            },
+            Some(c) if c.is_ascii_digit() || is_special_parameter(c) => {
+                self.pos += 1;
+                Ok(Some(WordPart::Parameter(ParamExpansion { name: c.to_string(), index: None, op: ParamOp::Value })))
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Reads `((expression))` from its first parenthesis. Returns `None` and
+    /// leaves the position alone when the parentheses do not close with `))`,
+    /// as in `$( (a) | b)`.
+    fn read_arithmetic(&mut self)-> Result<Option<Word>, ParseError>{
+        let start = self.pos;
+        self.pos += 2;
+        let mut depth = 0;
+        loop {
+            match self.next() {
+                Some('(') => depth += 1,
+                Some(')') if depth > 0 => depth -= 1,
+                Some(')') if self.peek() == Some(')') => {
+                    self.pos += 1;
+                    break;
+                },
+                Some(')') => {
+                    self.pos = start;
+                    return Ok(None);
+                },
+                Some(_) => {},
+                None => return Err(ParseError::Incomplete("unexpected EOF while looking for matching `))'".to_string())),
+            }
+        }
+        let text: String = self.chars[start + 2..self.pos - 2].iter().collect();
+        Ok(Some(arithmetic_word(&text)?))
+    }
+
+    /// Reads the command list of `$(...)` after the opening parenthesis,
+    /// including the closing one, and parses it. The list ends at the first
+    /// `)` the grammar does not expect, so the `)` of a `case` pattern or of
+    /// a subshell inside it is read as part of the commands.
+    fn read_command_substitution(&mut self)-> Result<WordPart, ParseError>{
+        let start = self.pos;
+        loop {
+            match self.next() {
+                Some('\\') => { self.next(); },
+                Some('\'') => { self.read_single_quoted()?; },
+                Some('"') => { self.read_double_quoted()?; },
+                Some('`') => { self.read_backquoted()?; },
+                Some(')') => {
+                    let text: String = self.chars[start..self.pos - 1].iter().collect();
+                    match parse_program(&text) {
+                        Err(ParseError::Incomplete(_)) => {},
+                        program => return Ok(WordPart::CommandSubst(program?)),
+                    }
+                },
+                Some(_) => {},
+                None => return Err(ParseError::Incomplete("unexpected EOF while looking for matching `)'".to_string())),
+            }
+        }
+    }
+
+    /// Reads a backquoted command substitution after the opening backquote.
+    /// A backslash only escapes `$`, `` ` `` and another backslash here.
+    fn read_backquoted(&mut self)-> Result<WordPart, ParseError>{
+        let mut text = String::new();
+        loop {
+            match self.next() {
+                Some('`') => break,
+                Some('\\') => match self.next() {
+                    Some(next @ ('$' | '`' | '\\')) => text.push(next),
+                    Some(next) => {
+                        text.push('\\');
+                        text.push(next);
+                    },
+                    None => text.push('\\'),
+                },
+                Some(c) => text.push(c),
+                None => return Err(ParseError::Incomplete("unexpected EOF while looking for matching ``'".to_string())),
+            }
+        }
+        Ok(WordPart::CommandSubst(parse_substitution(&text)?))
+    }
+
+    /// Reads the inside of `${...}` after the opening brace, including the
+    /// closing one.
+    fn read_braced_parameter(&mut self)-> Result<ParamExpansion, ParseError>{
+        let length = self.peek() == Some('#') && self.peek_at(1).is_some_and(|c| c != '}');
+        if length {
+            self.pos += 1;
+        }
+        let (name, index) = self.read_parameter_name()?;
+        if length {
+            if self.next() != Some('}') {
+                return Err(self.bad_substitution());
+            }
+            return Ok(ParamExpansion { name, index, op: ParamOp::Length });
+        }
+        let op = match self.next() {
+            Some('}') => return Ok(ParamExpansion { name, index, op: ParamOp::Value }),
+            Some(':') if matches!(self.peek(), Some('-' | '=' | '?' | '+')) => {
+                let op = self.next();
+                let word = self.read_parameter_word(false)?;
+                word_operator(op, true, word)
+            },
+            Some(':') => {
+                let offset = arithmetic_word(&self.read_arithmetic_text(true)?)?;
+                let length = if self.peek() == Some(':') {
+                    self.pos += 1;
+                    Some(arithmetic_word(&self.read_arithmetic_text(false)?)?)
+                }else{
+                    None
+                };
+                ParamOp::Substring { offset, length }
+            },
+            Some(c @ ('-' | '=' | '?' | '+')) => {
+                let word = self.read_parameter_word(false)?;
+                word_operator(Some(c), false, word)
+            },
+            Some(c @ ('#' | '%')) => {
+                let longest = self.peek() == Some(c);
+                if longest {
+                    self.pos += 1;
+                }
+                let pattern = self.read_parameter_word(false)?;
+                if c == '#' {
+                    ParamOp::RemovePrefix { longest, pattern }
+                }else{
+                    ParamOp::RemoveSuffix { longest, pattern }
+                }
+            },
+            Some('/') => {
+                let mode = match self.peek() {
+                    Some('/') => ReplaceMode::All,
+                    Some('#') => ReplaceMode::Prefix,
+                    Some('%') => ReplaceMode::Suffix,
+                    _ => ReplaceMode::First,
+                };
+                if mode != ReplaceMode::First {
+                    self.pos += 1;
+                }
+                let pattern = self.read_parameter_word(true)?;
+                let replacement = if self.peek() == Some('/') {
+                    self.pos += 1;
+                    self.read_parameter_word(false)?
+                }else{
+                    Word::default()
+                };
+                ParamOp::Replace { mode, pattern, replacement }
+            },
+            None => return Err(ParseError::Incomplete("unexpected EOF while looking for matching `}'".to_string())),
+            Some(_) => return Err(self.bad_substitution()),
+        };
+        match self.next() {
+            Some('}') => Ok(ParamExpansion { name, index, op }),
+            _ => Err(ParseError::Incomplete("unexpected EOF while looking for matching `}'".to_string())),
+        }
+    }
+
+    /// Reads a variable name, a special parameter, a positional parameter of
+    /// any number of digits, or an array element such as `name[@]`.
+    fn read_parameter_name(&mut self)-> Result<(String, Option<Word>), ParseError>{
+        let start = self.pos;
+        match self.peek() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_') {
+                    self.pos += 1;
+                }
+            },
+            Some(c) if c.is_ascii_digit() => {
+                while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                    self.pos += 1;
+                }
+            },
+            Some(c) if is_special_parameter(c) => self.pos += 1,
+            _ => return Err(self.bad_substitution()),
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+        if self.peek() != Some('[') || !is_variable_name(&name) {
+            return Ok((name, None));
+        }
+        self.pos += 1;
+        let index_start = self.pos;
+        let mut depth = 0;
+        loop {
+            match self.next() {
+                Some('[') => depth += 1,
+                Some(']') if depth == 0 => break,
+                Some(']') => depth -= 1,
+                Some(_) => {},
+                None => return Err(ParseError::Incomplete("unexpected EOF while looking for matching `]'".to_string())),
+            }
+        }
+        let index: String = self.chars[index_start..self.pos - 1].iter().collect();
+        Ok((name, Some(arithmetic_word(&index)?)))
+    }
+
+    /// Reads the word operand of a parameter operator up to the closing
+    /// brace, or up to a `/` when reading a replacement pattern. Blanks are
+    /// part of the word; quotes and expansions work as in any word.
+    fn read_parameter_word(&mut self, stop_at_slash: bool)-> Result<Word, ParseError>{
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                None => return Err(ParseError::Incomplete("unexpected EOF while looking for matching `}'".to_string())),
+                Some('}') if depth == 0 => break,
+                Some('/') if stop_at_slash && depth == 0 => break,
+                Some('\\') => {
+                    self.pos += 1;
+                    if let Some(escaped) = self.next() {
+                        flush_literal(&mut literal, &mut parts);
+                        parts.push(WordPart::Escaped(escaped));
+                    }
+                },
+                Some('\'') => {
+                    self.pos += 1;
+                    flush_literal(&mut literal, &mut parts);
+                    parts.push(WordPart::SingleQuoted(self.read_single_quoted()?));
+                },
+                Some('"') => {
+                    self.pos += 1;
+                    flush_literal(&mut literal, &mut parts);
+                    parts.push(WordPart::DoubleQuoted(self.read_double_quoted()?));
+                },
+                Some('`') => {
+                    self.pos += 1;
+                    flush_literal(&mut literal, &mut parts);
+                    parts.push(self.read_backquoted()?);
+                },
+                Some('$') => match self.read_dollar()? {
+                    Some(part) => {
+                        flush_literal(&mut literal, &mut parts);
+                        parts.push(part);
+                    },
+                    None => literal.push('$'),
+                },
+                Some(c) => {
+                    if c == '{' {
+                        depth += 1;
+                    }else if c == '}' {
+                        depth -= 1;
+                    }
+                    self.pos += 1;
+                    literal.push(c);
+                }
+            }
+        }
+        flush_literal(&mut literal, &mut parts);
+        Ok(Word { parts })
+    }
+
+    /// Reads the raw text of a substring offset or length, which is
+    /// evaluated later, up to the closing brace or (for the offset) a `:`.
+    fn read_arithmetic_text(&mut self, stop_at_colon: bool)-> Result<String, ParseError>{
+        let start = self.pos;
+        let mut depth = 0;
+        loop {
+            match self.peek() {
+                None => return Err(ParseError::Incomplete("unexpected EOF while looking for matching `}'".to_string())),
+                Some('}') if depth == 0 => break,
+                Some(':') if stop_at_colon && depth == 0 => break,
+                Some('(' | '{') => depth += 1,
+                Some(')' | '}') => depth -= 1,
+                _ => {}
+            }
+            self.pos += 1;
+        }
+        Ok(self.chars[start..self.pos].iter().collect())
+    }
+
+    fn bad_substitution(&self)-> ParseError{
+        ParseError::Syntax("bad substitution".to_string())
+    }
+}
+
+/// Parses the commands of a substitution. The substitution itself is
+/// complete, so a command cut short inside it is a syntax error.
+fn parse_substitution(text: &str)-> Result<Program, ParseError>{
+    parse_program(text).map_err(|e| match e {
+        ParseError::Incomplete(message) => ParseError::Syntax(message),
+        e => e,
+    })
+}
+
+/// Parses the text of an arithmetic expression, in which only parameter
+/// expansions, command substitutions and backslashes are special.
+fn arithmetic_word(text: &str)-> Result<Word, ParseError>{
+    let mut lexer = Lexer { chars: text.chars().collect(), pos: 0, pending_here_docs: Vec::new() };
+    Ok(Word { parts: lexer.read_here_doc_text()? })
+}
+
+fn is_variable_name(name: &str)-> bool{
+    name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+}
+
+/// Builds the operator of `${name-word}` and its `=`, `?` and `+` variants.
+fn word_operator(op: Option<char>, colon: bool, word: Word)-> ParamOp{
+    match op {
+        Some('=') => ParamOp::Assign { colon, word },
+        Some('?') => ParamOp::Error { colon, word },
+        Some('+') => ParamOp::Alternative { colon, word },
+        _ => ParamOp::Default { colon, word },
+    }
+}
+
+/// Single-character special parameters such as `$?`.
+fn is_special_parameter(c: char)-> bool{
+    matches!(c, '?' | '#' | '@' | '*' | '$' | '!' | '-')
+}
+
+/// Removes quotes and backslashes from the raw text of a word, as needed
+/// for here-document delimiters.
+fn remove_quotes(raw: &str)-> String{
+    let mut text = String::new();
+    let mut chars = raw.chars();
+    let mut in_single = false;
+    let mut in_double = false;
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' if !in_double => in_single = !in_single,
+            '"' if !in_single => in_double = !in_double,
+            '\\' if !in_single => match chars.next() {
+                Some(next) if !in_double || matches!(next, '"' | '\\' | '$' | '`') => text.push(next),
+                Some(next) => {
+                    text.push('\\');
+                    text.push(next);
+                },
+                None => text.push('\\'),
+            },
+            _ => text.push(c),
+        }
+    }
+    text
+}
+
+/// Characters that end an unquoted word. This includes `&`, whether it
+/// runs a list in the background or starts `&&`, `&>` or `&>>`.
+fn is_metachar(c: char)-> bool{
+    matches!(c, ' ' | '\t' | '\n' | '|' | '>' | '<' | ';' | '(' | ')' | '&')
+}
+
+fn flush_literal(literal: &mut String, parts: &mut Vec<WordPart>){
+    if !literal.is_empty() {
+        parts.push(WordPart::Literal(std::mem::take(literal)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str)-> Vec<Token>{
+        tokenize(input).unwrap().into_iter().map(|(token, _)| token).collect()
+    }
+
+    /// The unquoted words of a line, with `None` for anything else.
+    fn words(input: &str)-> Vec<Option<String>>{
+        tokens(input).iter().map(|token| match token {
+            Token::Word(word) => word.as_literal(),
+            _ => None,
+        }).collect()
+    }
+
+    fn is_incomplete(input: &str)-> bool{
+        matches!(tokenize(input), Err(ParseError::Incomplete(_)))
+    }
+
+    #[test]
+    fn splits_words_and_operators(){
+        let tokens = tokens("a|b >f 2>>g");
+        assert!(matches!(tokens[1], Token::Operator(Operator::Pipe)));
+        assert!(matches!(tokens[3], Token::Operator(Operator::Great)));
+        assert!(matches!(tokens[6], Token::Operator(Operator::DGreat)));
+        assert_eq!(words("a|b >f").into_iter().flatten().collect::<Vec<_>>(), ["a", "b", "f"]);
+    }
+
+    #[test]
+    fn reads_io_numbers_only_before_redirections(){
+        assert!(matches!(tokens("echo 2>f")[1], Token::IoNumber(2)));
+        assert_eq!(words("echo 2 >f")[1].as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn joins_lines_at_backslash_newline(){
+        assert_eq!(words("echo a\\\nb")[1].as_deref(), Some("ab"));
+    }
+
+    #[test]
+    fn detects_unclosed_quotes(){
+        assert!(is_incomplete("echo 'a"));
+        assert!(is_incomplete("echo \"a"));
+        assert!(!is_incomplete("echo 'a' \"b\" \\c"));
+    }
+
+    #[test]
+    fn reads_list_operators(){
+        let tokens = tokens("a && c;d");
+        assert!(matches!(tokens[1], Token::Operator(Operator::AndIf)));
+        assert!(matches!(tokens[3], Token::Operator(Operator::Semi)));
+        assert_eq!(words("a && c;d").into_iter().flatten().collect::<Vec<_>>(), ["a", "c", "d"]);
+    }
+
+    #[test]
+    fn detects_unfinished_lines_and_here_documents(){
+        assert!(is_incomplete("echo \\"));
+        assert!(is_incomplete("cat <<EOF\nbody\n"));
+        assert!(!is_incomplete("cat <<EOF\nbody\nEOF\n"));
+        assert!(!is_incomplete("cat <<-EOF\n\tbody\n\tEOF\n"));
+    }
+
+    #[test]
+    fn detects_unfinished_parameter_expansions(){
+        assert!(is_incomplete("echo ${a"));
+        assert!(is_incomplete("echo \"${a:-b\""));
+        assert!(!is_incomplete("echo ${a:-b}"));
+    }
+
+    #[test]
+    fn detects_unfinished_command_substitutions(){
+        assert!(is_incomplete("echo $(date"));
+        assert!(is_incomplete("echo `date"));
+        assert!(!is_incomplete("echo $(date) `date`"));
+    }
+
+    #[test]
+    fn reads_nested_command_substitutions(){
+        assert_eq!(tokens("echo $(echo $(echo b)) x").len(), 3);
+        assert_eq!(tokens("echo \"$(echo \")\")\" x").len(), 3);
+    }
+
+    #[test]
+    fn skips_comments(){
+        assert_eq!(words("echo a # b c"), [Some("echo".to_string()), Some("a".to_string())]);
+        assert_eq!(words("echo a#b")[1].as_deref(), Some("a#b"));
+    }
+
+    #[test]
+    fn skips_backslash_newline_between_words(){
+        assert_eq!(words("echo a \\\n  b").into_iter().flatten().collect::<Vec<_>>(), ["echo", "a", "b"]);
+    }
+
+    #[test]
+    fn backslash_newline_at_end_is_incomplete(){
+        assert!(is_incomplete("echo one \\\n"));
+        assert!(is_incomplete("echo one\\\n"));
+        assert!(is_incomplete("echo \"one\\\n"));
+        assert!(!is_incomplete("echo one \\\ntwo\n"));
+    }
+
+    #[test]
+    fn ends_command_substitution_where_the_grammar_does(){
+        assert_eq!(tokens("echo $(case x in x) echo y;; esac) z").len(), 3);
+        assert_eq!(tokens("echo $( (echo a) ) $(echo $(echo b))").len(), 3);
+        assert!(is_incomplete("echo $(case x in x) echo y;;"));
+    }
+}
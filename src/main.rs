@@ -1,14 +1,26 @@
 use std::{env, fs};
-use std::process::Child;
 use std::result::Result::Ok;
 use std::io::{self, Write};
-use std::{ fs::{File, OpenOptions}, process::{Command}};
-use rustyline::{CompletionType, Config, Editor, Helper, completion::{Completer, Pair}, highlight::Highlighter, hint::Hinter};
+use rustyline::{CompletionType, error::ReadlineError, Config, Editor, Helper, completion::{Completer, Pair}, highlight::Highlighter, hint::Hinter};
 
-use crate::executor::run_command;
-use crate::parser::{parse_command};
+use crate::executor::{run_exit_trap, run_program};
+use crate::lexer::ParseError;
+use crate::parser::parse_program;
+use crate::shell::{Shell, ShellOptions};
+mod arith;
+mod ast;
+mod lexer;
 mod parser;
+mod expand;
+mod brace;
+mod conditional;
+mod glob;
+mod jobs;
+mod signals;
+mod vars;
+mod redirect;
 mod executor;
+mod shell;
 
 pub struct MyHelper{
     commands: Vec<String>
@@ -20,10 +32,13 @@ pub enum HistoryAction{
     Append
 }
 
+/// Outcome of a command. Every variant carries an exit status except
+/// `ModifyHistory`, whose status depends on whether the shell manages to
+/// carry out the requested history action.
 pub enum CommandResult{
-    Output (String, String),
-    Exit,
-    NoOp,
+    Output (String, String, i32),
+    Exit (i32),
+    NoOp (i32),
     ModifyHistory (Option<String>, HistoryAction)
 }
 
@@ -56,291 +71,318 @@ impl Highlighter for MyHelper {}
 
 impl Helper for MyHelper {}
 
-struct ParsedResult{
-    commands: Vec<Vec<String>>,
-    output_file: Vec<String>,
-    error_file: Vec<String>,
-    redirect_as_output: bool,
-    redirect_as_error: bool,
-    append_as_output: bool,
-    append_as_error: bool
+/// Where the shell reads its commands from.
+enum Source{
+    /// The string given to `-c`.
+    Command(String),
+    /// A script file named on the command line.
+    File(String),
+    /// Standard input: a terminal gets a prompt, anything else is read as a
+    /// script.
+    Stdin
+}
+
+/// What the command line asks the shell to run.
+struct Invocation{
+    source: Source,
+    /// `$0`: the script path, the name given after `-c string`, or the
+    /// shell's own name.
+    name: String,
+    /// The positional parameters `$1` onwards.
+    args: Vec<String>,
+    /// Options given as for `set`, such as `-e` or `-o pipefail`.
+    options: ShellOptions
 }
 
 fn main() {
+    let argv: Vec<String> = env::args().collect();
+    let shell_name = argv.first().cloned().unwrap_or_else(|| "rust-shell".to_string());
+    let invocation = match parse_invocation(&argv) {
+        Ok(invocation) => invocation,
+        Err(e) => {
+            eprintln!("{}: {}", shell_name, e);
+            eprintln!("Usage: {} [-efnuxC] [-o option] [-s] [-c command [name [arg ...]]] [script [arg ...]]", shell_name);
+            std::process::exit(2);
+        }
+    };
+    // Take the character set from the environment, so that regular
+    // expressions in `[[ =~ ]]` match whole characters.
+    unsafe { libc::setlocale(libc::LC_ALL, c"".as_ptr()) };
+    let mut shell = Shell::new();
+    shell.options = invocation.options;
+    shell.script_name = invocation.name;
+    shell.positional_params = invocation.args;
+    let status = match invocation.source {
+        Source::Command(command) => run_script(command.lines().map(str::to_string), &format!("{}: -c", shell_name), &mut shell),
+        Source::File(path) => match fs::read_to_string(&path) {
+            Ok(script) => run_script(script.lines().map(str::to_string), &path, &mut shell),
+            Err(e) => {
+                eprintln!("{}: {}: {}", shell_name, path, e);
+                if e.kind() == io::ErrorKind::NotFound { 127 } else { 126 }
+            }
+        },
+        Source::Stdin if unsafe { libc::isatty(0) } == 1 => run_interactive(&mut shell),
+        Source::Stdin => run_script(std::iter::from_fn(read_stdin_line), &shell_name, &mut shell),
+    };
+    shell.last_status = status;
+    run_exit_trap(&mut shell);
+    std::process::exit(shell.last_status);
+}
+
+/// Parses the shell's own arguments: `-c string [name [args]]`, `-s [args]`,
+/// or `script [args]`, with `--` ending the options. The options of `set`
+/// may be given as well.
+///
+/// # Arguments
+/// * `argv` - The arguments of the process, including the shell's name
+///
+/// # Returns
+/// * `Result<Invocation, String>` - What to run, or why the arguments are invalid
+fn parse_invocation(argv: &[String])-> Result<Invocation, String>{
+    let mut command = false;
+    let mut read_stdin = false;
+    let mut options = ShellOptions::default();
+    let mut rest = argv.iter().skip(1).peekable();
+    while let Some(arg) = rest.next_if(|arg| arg.starts_with(['-', '+']) && arg.len() >= 2) {
+        if arg == "--" {
+            break;
+        }
+        let enable = arg.starts_with('-');
+        for c in arg[1..].chars() {
+            match c {
+                'c' if enable => command = true,
+                's' if enable => read_stdin = true,
+                'o' => {
+                    let name = rest.next().ok_or_else(|| format!("{}o: option requires an argument", &arg[..1]))?;
+                    *options.option_mut(name).ok_or_else(|| format!("{}: invalid option name", name))? = enable;
+                },
+                _ => match options.letter_mut(c) {
+                    Some(option) => *option = enable,
+                    None => return Err(format!("{}{}: invalid option", &arg[..1], c)),
+                },
+            }
+        }
+    }
+    let mut rest: Vec<String> = rest.cloned().collect();
+    let shell_name = argv.first().cloned().unwrap_or_default();
+    if command {
+        if rest.is_empty() {
+            return Err("-c: option requires an argument".to_string());
+        }
+        let text = rest.remove(0);
+        let name = if rest.is_empty() { shell_name } else { rest.remove(0) };
+        return Ok(Invocation { source: Source::Command(text), name, args: rest, options });
+    }
+    if read_stdin || rest.is_empty() {
+        return Ok(Invocation { source: Source::Stdin, name: shell_name, args: rest, options });
+    }
+    let path = rest.remove(0);
+    Ok(Invocation { source: Source::File(path.clone()), name: path, args: rest, options })
+}
+
+/// Runs commands from a script, `-c` string or piped input without a
+/// prompt. Each command runs as soon as its last line has been read, so a
+/// syntax error further on does not stop the commands before it; the error
+/// itself ends the script with status 2.
+///
+/// # Arguments
+/// * `lines` - The lines of the script, without their newlines
+/// * `name` - How error messages refer to the script
+/// * `shell` - Shell state
+///
+/// # Returns
+/// * `i32` - The status the shell exits with: that of the last command
+fn run_script(lines: impl Iterator<Item = String>, name: &str, shell: &mut Shell)-> i32{
+    let mut source = String::new();
+    for (number, line) in lines.enumerate() {
+        source.push_str(&line);
+        source.push('\n');
+        match parse_program(&source) {
+            Err(ParseError::Incomplete(_)) => continue,
+            Ok(program) => run_program(&program, shell),
+            Err(e) => {
+                eprintln!("{}: line {}: {}", name, number + 1, e);
+                return 2;
+            }
+        }
+        source.clear();
+        shell.history_actions.clear();
+        shell.jobs.update();
+        if shell.exit_requested {
+            return shell.last_status;
+        }
+    }
+    // A backslash-newline at the very end of the input continues onto
+    // nothing.
+    if let Some(rest) = source.strip_suffix("\\\n") {
+        if let Ok(program) = parse_program(rest) {
+            run_program(&program, shell);
+            return shell.last_status;
+        }
+    }
+    if let Err(e @ ParseError::Incomplete(_)) = parse_program(&source) {
+        eprintln!("{}: {}", name, e);
+        return 2;
+    }
+    shell.last_status
+}
+
+/// Reads a line from standard input one byte at a time, so that nothing
+/// after it is consumed and commands of the script can read the rest.
+fn read_stdin_line()-> Option<String>{
+    let mut line = Vec::new();
+    loop {
+        let mut byte = 0u8;
+        match unsafe { libc::read(0, &mut byte as *mut u8 as *mut libc::c_void, 1) } {
+            1 if byte == b'\n' => break,
+            1 => line.push(byte),
+            -1 if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted => {},
+            _ if line.is_empty() => return None,
+            _ => break,
+        }
+    }
+    Some(String::from_utf8_lossy(&line).into_owned())
+}
+
+/// Runs the interactive prompt until `exit` or end of input.
+///
+/// # Arguments
+/// * `shell` - Shell state
+///
+/// # Returns
+/// * `i32` - The status the shell exits with
+fn run_interactive(shell: &mut Shell)-> i32{
+    shell.interactive = true;
+    signals::handle_interactive();
+    shell.jobs.enable();
     let mut last_saved_count = 0;
     let config = Config::builder()
         .completion_type(CompletionType::List)
         .build();
-    let built_ins: Vec<String> = vec!["echo", "exit", "type", "pwd", "cd", "history"]
-        .into_iter()
-        .map(|s| s.to_string())
-        .collect();
-    let mut all_commands: Vec<String> = built_ins.clone();
+    let mut all_commands: Vec<String> = shell.built_ins.clone();
     all_commands.extend(get_all_commands());
     let helper = MyHelper { commands:all_commands.clone() };
     let mut rl = Editor::with_config(config);
     rl.set_helper(Some(helper));
-    if let Ok(histfile) = env::var("HISTFILE") {
+    if let Some(histfile) = shell.vars.get_scalar("HISTFILE") {
         let _ = rl.load_history(&histfile);
     }
-    'shell: loop{
+    let mut full_command = String::new();
+    loop{
+        shell.jobs.notify();
         io::stdout().flush().unwrap();
-        let full_command = rl.readline("$ ");
-        match full_command {
-            Ok(full_command) => {
+        let prompt = if full_command.is_empty() { "$ " } else { "> " };
+        match rl.readline(prompt) {
+            Ok(line) => {
+                if !full_command.is_empty() {
+                    full_command.push('\n');
+                }
+                full_command.push_str(line.strip_suffix('\n').unwrap_or(&line));
+                let parsed = parse_program(&full_command);
+                if let Err(ParseError::Incomplete(_)) = parsed {
+                    continue;
+                }
                 let _ = rl.add_history_entry(full_command.as_str());
-                let history_vec = rl.history().iter().map(|s| s.to_string()).collect();
-                let parsed_result = parse_command(&full_command);
-                if parsed_result.commands.len() > 1{
-                    use std::process::Stdio;
-                    let mut last_child: Option<Child> = None;
-                    let mut previous_output: Option<String> = None;
-
-                    for (i, cmd_parts) in parsed_result.commands.iter().enumerate(){
-                        let is_builtin = built_ins.contains(&cmd_parts[0]);
-
-                        if !is_builtin{
-                            let mut cmd = Command::new(&cmd_parts[0]);
-                            cmd.args(&cmd_parts[1..]);
-
-                            if i > 0{
-                                if let Some(mut prev_child) = last_child.take(){
-                                    let prev_stdout = prev_child.stdout.take().unwrap();
-                                    cmd.stdin(prev_stdout);
-                                }else if previous_output.is_some(){
-                                    cmd.stdin(Stdio::piped());
-                                }
-                            }
-                            
-                            let needs_builtin_input = i > 0 && previous_output.is_some();
-
-                            if i < parsed_result.commands.len() - 1 {
-                                cmd.stdout(Stdio::piped());
-                            }
-
-                            match cmd.spawn(){
-                                Ok(mut child) =>{
-                                    if needs_builtin_input{
-                                        if let Some (prev_out) = previous_output.take() {
-                                            if let Some (mut stdin) = child.stdin.take(){
-                                                let _ = stdin.write_all(prev_out.as_bytes());
-                                                drop(stdin);
-                                            }
-                                        }
-                                    }
-                                    last_child = Some(child);
-                                },
-                                Err(e) =>{
-                                    eprint!("Error while trying to spawn command: {}", e);
-                                }
-                            }
-                        }else{
-                            match run_command(cmd_parts, &parsed_result, &built_ins, &history_vec){
-                                CommandResult::Output(output, _error_output)=>{
-                                    previous_output = Some(output);
-                                    last_child = None;
-                                },
-                                CommandResult::NoOp => {
-                                    previous_output = None;
-                                    last_child = None;
-                                },
-                                CommandResult::Exit => {
-                                    if let Ok(histfile) = env::var("HISTFILE") {
-                                        let _ = rl.save_history(&histfile);
-                                    }
-                                    break 'shell
-                                },
-                                CommandResult::ModifyHistory(path, action) => {
-                                    let history_path = path.unwrap_or_else(|| ".shell_history".to_string());
-                                    match action {
-                                        HistoryAction::Read => {
-                                            use std::fs::File;
-                                            use std::io::{BufRead, BufReader};
-
-                                            match File::open(&history_path){
-                                                Ok(file) => {
-                                                    for line in BufReader::new(file).lines().flatten(){
-                                                        eprintln!("DEBUG: Loading line: {:?}", &line);
-                                                        let _ = rl.add_history_entry(line);
-                                                    }
-                                                },
-                                                Err (e) =>{
-                                                    eprintln!("Error while opening the file: {}", e);
-                                                }
-                                            }
-                                        },
-                                        HistoryAction::Write =>{
-                                            if let Err(e) = rl.save_history(&history_path){
-                                                eprintln!("Error while loading history: {}", e);
-                                            }
-                                        },
-                                        HistoryAction::Append => {
-                                            use std::fs::OpenOptions;
-
-                                            match OpenOptions::new().create(true).append(true).open(&history_path){
-                                                Ok(mut file) => {
-                                                    for entry in rl.history().iter().skip(last_saved_count){
-                                                        if let Err(e) = writeln!(file, "{}", entry){
-                                                            eprintln!("history: {}", e);
-                                                        }
-                                                    }
-                                                    last_saved_count = rl.history().len();
-                                                },
-                                                Err (e) =>{
-                                                    eprintln!("Error while opening the file: {}", e);
-                                                }
-                                            }
-                                        }
-                                    }
-                                    previous_output = None;
-                                    last_child = None;
-                                }
-                            }
-                        }
-                    }
-
-                    if let Some(last) = last_child{
-                        match last.wait_with_output(){
-                            Ok(cmd_output) => {
-                                print!("{}", String::from_utf8_lossy(&cmd_output.stdout));
-                                if !cmd_output.stderr.is_empty() {
-                                    eprint!("{}", String::from_utf8_lossy(&cmd_output.stderr));
-                                }
-                            },
-                            Err(e) =>{
-                                eprintln!("{}", e);
-                            }
-                        }
-                    } else if let Some(output) = previous_output{
-                        print!("{}", output);
+                full_command.clear();
+                shell.history = rl.history().iter().map(|s| s.to_string()).collect();
+                match parsed{
+                    Ok(program) => run_program(&program, shell),
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        shell.set_pipeline_status(vec![2]);
                     }
-                    continue;
                 }
-                match run_command(&parsed_result.commands[0], &parsed_result, &built_ins, &history_vec){
-                    CommandResult::Output(output, error_output) =>{
-                        if !parsed_result.redirect_as_output && !parsed_result.append_as_output && !output.is_empty() {
-                            print!("{}", output);
-                        }
-                        if !parsed_result.redirect_as_error && !parsed_result.append_as_error && !error_output.is_empty() {
-                            eprint!("{}", error_output);
-                        }
-                        if parsed_result.redirect_as_output {
-                            for file_name in &parsed_result.output_file{
-                                match File::create(file_name){
-                                    Ok(mut file) => {
-                                        if let Err(e) = file.write_all(output.as_bytes()){
-                                            eprintln!("Error while writing to file: {}",e);
-                                        }
-                                    },
-                                    Err(e) => {
-                                        eprintln!("Error while creating the file: {}", e);
-                                    }
-                                }
-                            }
-                        }
-                        if parsed_result.redirect_as_error {
-                            for file_name in &parsed_result.error_file{
-                                match File::create(file_name){
-                                    Ok(mut file) => {
-                                        if let Err(e) = file.write_all(error_output.as_bytes()){
-                                            eprint!("Error while writing to file: {}", e);
-                                        }
-                                    },
-                                    Err(e) => {
-                                        eprintln!("Error while creating the file: {}", e);
-                                    }
-                                }
-                            }
-                        }
-                        if parsed_result.append_as_output {
-                            for file_name in &parsed_result.output_file{
-                                match OpenOptions::new().create(true).append(true).open(file_name){
-                                    Ok(mut file) =>{
-                                        if let Err(e ) = file.write_all(output.as_bytes()){
-                                            eprintln!("Error while appending to file: {}", e);
-                                        }
-                                    }
-                                    Err (e) => {
-                                        eprintln!("Error while opening the file: {}", e);
-                                    }
-                                }
-                            }
-                        }
-                        if parsed_result.append_as_error {
-                            for file_name in &parsed_result.error_file{
-                                match OpenOptions::new().create(true).append(true).open(file_name){
-                                    Ok(mut file) =>{
-                                        if let Err(e ) = file.write_all(error_output.as_bytes()){
-                                            eprintln!("Error while appending to file: {}", e);
-                                        }
-                                    }
-                                    Err (e) => {
-                                        eprintln!("Error while opening the file: {}", e);
-                                    }
-                                }
-                            }
-                        }
-                    },
-                    CommandResult::NoOp => continue,
-                    CommandResult::Exit => {
-                        if let Ok(histfile) = env::var("HISTFILE") {
-                            let _ = rl.save_history(&histfile);
-                        }   
-                        break
-                    },
-                    CommandResult::ModifyHistory(path, action) => {
-                        let history_path = path.unwrap_or_else(|| ".shell_history".to_string());
-                        match action {
-                            HistoryAction::Read => {
-                                use std::fs::File;
-                                use std::io::{BufRead, BufReader};
-
-                                match File::open(&history_path){
-                                    Ok(file) => {
-                                        for line in BufReader::new(file).lines().flatten(){
-                                            let _ = rl.add_history_entry(line);
-                                        }
-                                    },
-                                    Err (e) =>{
-                                        eprintln!("Error while opening the file: {}", e);
-                                    }
-                                }
-                            },
-                            HistoryAction::Write => {
-                                if let Err(e) = rl.save_history(&history_path){
-                                    eprintln!("Error while loading history: {}", e);
-                                }
-                            },
-                            HistoryAction::Append =>{
-                                 use std::fs::OpenOptions;
-
-                                match OpenOptions::new().create(true).append(true).open(&history_path){
-                                    Ok(mut file) => {
-                                        for entry in rl.history().iter().skip(last_saved_count){
-                                            if let Err(e) = writeln!(file, "{}", entry){
-                                                eprintln!("history: {}", e);
-                                            }
-                                        }
-                                        last_saved_count = rl.history().len();
-                                    },
-                                    Err (e) =>{
-                                        eprintln!("Error while opening the file: {}", e);
-                                    }
-                                }
-                            }
-                        }
+                if signals::take_interrupt() {
+                    eprintln!();
+                    shell.last_status = 130;
+                }
+                for (path, action) in std::mem::take(&mut shell.history_actions) {
+                    if modify_history(&mut rl, path, action, &mut last_saved_count) != 0 {
+                        shell.last_status = 1;
                     }
                 }
+                if shell.exit_requested {
+                    break;
+                }
             }
+            Err(ReadlineError::Interrupted) => {
+                full_command.clear();
+                shell.last_status = 130;
+            },
             Err(e) => {
+                if let Err(e @ ParseError::Incomplete(_)) = parse_program(&full_command) {
+                    eprintln!("{}", e);
+                    shell.last_status = 2;
+                }
                 eprintln!("Error: {}", e);
                 break;
             },
         }
     }
-    if let Ok(histfile) = env::var("HISTFILE") {
+    if let Some(histfile) = shell.vars.get_scalar("HISTFILE") {
         let _ = rl.save_history(&histfile);
     }
+    shell.last_status
+}
+
+/// Carries out a `history -r/-w/-a` request on the line editor's history.
+///
+/// # Arguments
+/// * `rl` - The line editor owning the history
+/// * `path` - History file, `.shell_history` when not given
+/// * `action` - What to do with the file
+/// * `last_saved_count` - Number of entries already appended by `history -a`
+///
+/// # Returns
+/// * `i32` - The exit status of the request
+fn modify_history(rl: &mut Editor<MyHelper>, path: Option<String>, action: HistoryAction, last_saved_count: &mut usize)-> i32{
+    let history_path = path.unwrap_or_else(|| ".shell_history".to_string());
+    match action {
+        HistoryAction::Read => {
+            use std::fs::File;
+            use std::io::{BufRead, BufReader};
+
+            match File::open(&history_path){
+                Ok(file) => {
+                    for line in BufReader::new(file).lines().map_while(Result::ok){
+                        let _ = rl.add_history_entry(line);
+                    }
+                    0
+                },
+                Err (e) =>{
+                    eprintln!("Error while opening the file: {}", e);
+                    1
+                }
+            }
+        },
+        HistoryAction::Write => {
+            if let Err(e) = rl.save_history(&history_path){
+                eprintln!("Error while loading history: {}", e);
+                1
+            }else{
+                0
+            }
+        },
+        HistoryAction::Append =>{
+            use std::fs::OpenOptions;
+
+            match OpenOptions::new().create(true).append(true).open(&history_path){
+                Ok(mut file) => {
+                    for entry in rl.history().iter().skip(*last_saved_count){
+                        if let Err(e) = writeln!(file, "{}", entry){
+                            eprintln!("history: {}", e);
+                        }
+                    }
+                    *last_saved_count = rl.history().len();
+                    0
+                },
+                Err (e) =>{
+                    eprintln!("Error while opening the file: {}", e);
+                    1
+                }
+            }
+        }
+    }
 }
 
 /// Collects all executable commands from the system PATH.
@@ -381,4 +423,102 @@ fn get_all_commands()-> Vec<String>{
     all_commands.sort();
     all_commands.dedup();
     all_commands
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invocation(args: &[&str])-> Result<Invocation, String>{
+        let argv: Vec<String> = std::iter::once("rust-shell").chain(args.iter().copied()).map(str::to_string).collect();
+        parse_invocation(&argv)
+    }
+
+    /// Runs a script the way `main` does for an invocation, returning its
+    /// status and the shell.
+    fn run(args: &[&str], script: &[&str])-> (i32, Shell){
+        let invocation = invocation(args).unwrap();
+        let mut shell = Shell::new();
+        shell.options = invocation.options;
+        shell.script_name = invocation.name;
+        shell.positional_params = invocation.args;
+        let status = run_script(script.iter().map(|line| line.to_string()), "test", &mut shell);
+        (status, shell)
+    }
+
+    fn var(shell: &Shell, name: &str)-> Option<String>{
+        shell.vars.get_scalar(name)
+    }
+
+    #[test]
+    fn takes_the_name_and_arguments_after_c(){
+        let parsed = invocation(&["-c", "echo $0", "name", "a", "b"]).unwrap();
+        assert!(matches!(parsed.source, Source::Command(ref text) if text == "echo $0"));
+        assert_eq!(parsed.name, "name");
+        assert_eq!(parsed.args, ["a", "b"]);
+        let parsed = invocation(&["-c", "echo"]).unwrap();
+        assert_eq!(parsed.name, "rust-shell");
+        assert!(parsed.args.is_empty());
+        let (_, shell) = run(&["-c", "unused", "name", "a", "b c"], &["zero=$0 count=$# all=\"$*\""]);
+        assert_eq!(var(&shell, "zero").as_deref(), Some("name"));
+        assert_eq!(var(&shell, "count").as_deref(), Some("2"));
+        assert_eq!(var(&shell, "all").as_deref(), Some("a b c"));
+        assert_eq!(invocation(&["-c"]).err().as_deref(), Some("-c: option requires an argument"));
+    }
+
+    #[test]
+    fn reads_stdin_with_s(){
+        let parsed = invocation(&["-s", "a", "b"]).unwrap();
+        assert!(matches!(parsed.source, Source::Stdin));
+        assert_eq!(parsed.name, "rust-shell");
+        assert_eq!(parsed.args, ["a", "b"]);
+        assert!(matches!(invocation(&[]).unwrap().source, Source::Stdin));
+    }
+
+    #[test]
+    fn runs_scripts_with_their_arguments(){
+        let parsed = invocation(&["script.sh", "-x", "b"]).unwrap();
+        assert!(matches!(parsed.source, Source::File(ref path) if path == "script.sh"));
+        assert_eq!(parsed.name, "script.sh");
+        assert_eq!(parsed.args, ["-x", "b"]);
+        assert!(!parsed.options.xtrace);
+        let parsed = invocation(&["--", "-script", "a"]).unwrap();
+        assert!(matches!(parsed.source, Source::File(ref path) if path == "-script"));
+        assert_eq!(parsed.args, ["a"]);
+    }
+
+    #[test]
+    fn takes_set_options(){
+        let parsed = invocation(&["-eu", "-o", "pipefail", "+e", "-c", "true"]).unwrap();
+        assert!(!parsed.options.errexit && parsed.options.nounset && parsed.options.pipefail);
+        let parsed = invocation(&["-xc", "true", "name"]).unwrap();
+        assert!(parsed.options.xtrace);
+        assert_eq!(parsed.name, "name");
+        assert_eq!(invocation(&["-q"]).err().as_deref(), Some("-q: invalid option"));
+        assert_eq!(invocation(&["-o", "nosuch"]).err().as_deref(), Some("nosuch: invalid option name"));
+        assert_eq!(invocation(&["+o"]).err().as_deref(), Some("+o: option requires an argument"));
+    }
+
+    #[test]
+    fn joins_lines_ending_in_a_backslash(){
+        let (status, shell) = run(&["-s"], &["x=a\\", "b y=\\", "c"]);
+        assert_eq!(status, 0);
+        assert_eq!(var(&shell, "x").as_deref(), Some("ab"));
+        assert_eq!(var(&shell, "y").as_deref(), Some("c"));
+        let (status, shell) = run(&["-s"], &["x=1", "y=2 \\"]);
+        assert_eq!(status, 0);
+        assert_eq!(var(&shell, "y").as_deref(), Some("2"));
+    }
+
+    #[test]
+    fn stops_at_syntax_errors_and_exit(){
+        let (status, shell) = run(&["-s"], &["x=1", "fi", "y=2"]);
+        assert_eq!(status, 2);
+        assert_eq!(var(&shell, "x").as_deref(), Some("1"));
+        assert_eq!(var(&shell, "y"), None);
+        let (status, _) = run(&["-s"], &["if true; then"]);
+        assert_eq!(status, 2);
+        let (status, shell) = run(&["-s"], &["exit 3", "y=2"]);
+        assert_eq!(status, 3);
+        assert_eq!(var(&shell, "y"), None);
+    }
+}
@@ -1,150 +1,765 @@
-use crate::ParsedResult;
+use std::ops::Range;
 
-/// Parses a shell command line into commands, arguments, and redirections.
-/// Handles pipes, output/error redirection, and quoted strings.
+use crate::ast::{AndOrList, AndOrOp, Assignment, CaseItem, CaseTerminator, Command, CompoundCommand, ConditionalExpr, ParamExpansion, ParamOp, Pipeline, Program, Redirect, RedirectOp, SimpleCommand, Word, WordPart};
+use crate::conditional::{BINARY_OPERATORS, UNARY_OPERATORS};
+use crate::lexer::{tokenize, Operator, ParseError, Token};
+
+/// Parses a shell command line into a `Program`.
+/// Handles `;`/newline lists, `&&`/`||`, `!`, pipes, per-command
+/// redirections, assignments, quoted strings and compound commands.
 ///
 /// # Arguments
 /// * `input` - The command line string to parse
 ///
 /// # Returns
-/// * `ParsedResult` - The parsed structure for execution
-pub fn parse_command(input: &str)->ParsedResult{
-    let mut commands = Vec::new();
-    let mut args = Vec::new();
-    let mut arg = String::new();
-    let mut chars = input.chars().peekable();
-    let mut in_single = false;
-    let mut in_double = false;
-    let mut output_file:Vec<String>  = Vec::new();
-    let mut error_file:Vec<String>  = Vec::new();
-    let mut is_output_file_name = false;
-    let mut is_error_file_name = false;
-    let mut redirect_as_output = false;
-    let mut redirect_as_error = false;
-    let mut append_as_output = false;
-    let mut append_as_error = false;
-    while let Some(&c) = chars.peek(){
-        chars.next();
-        match c {
-            ' ' | '\t' | '\n' if !in_single && !in_double =>{
-                if !arg.is_empty() && !is_output_file_name && !is_error_file_name{
-                    args.push(arg);
-                    arg = String::new();
-                }else if !arg.is_empty() && is_output_file_name {
-                    output_file.push(arg.clone());
-                    is_output_file_name = false;
-                    arg = String::new();
-                }else if !arg.is_empty() && is_error_file_name{
-                    error_file.push(arg.clone());
-                    is_error_file_name = false;
-                    arg = String::new();
-                }
+/// * `Result<Program, ParseError>` - The syntax tree for execution
+pub fn parse_program(input: &str)-> Result<Program, ParseError>{
+    let (tokens, spans) = tokenize(input)?.into_iter().unzip();
+    let mut parser = Parser { tokens, spans, source: input.chars().collect(), pos: 0 };
+    let program = parser.parse_list()?;
+    match parser.peek() {
+        Some(token) => Err(unexpected(token)),
+        None => Ok(program),
+    }
+}
+
+/// Words that have a meaning to the grammar when they start a command.
+pub const RESERVED_WORDS: [&str; 18] = ["if", "then", "elif", "else", "fi", "while", "until", "for", "in", "do", "done", "case", "esac",
+    "function", "{", "}", "[[", "]]"];
+
+/// Reserved words that end a command list inside a compound command.
+const CLOSING_WORDS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+/// Reserved words that start a compound command.
+const COMPOUND_WORDS: [&str; 7] = ["{", "if", "while", "until", "for", "case", "[["];
+
+struct Parser{
+    tokens: Vec<Token>,
+    /// The range of `source` every token was read from.
+    spans: Vec<Range<usize>>,
+    source: Vec<char>,
+    pos: usize
+}
+
+impl Parser{
+    fn peek(&self)-> Option<&Token>{
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self)-> Option<Token>{
+        let token = self.tokens.get(self.pos).cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn skip_newlines(&mut self){
+        while matches!(self.peek(), Some(Token::Newline)) {
+            self.pos += 1;
+        }
+    }
+
+    /// Whether the next token is the unquoted reserved word `word`.
+    fn at_reserved(&self, word: &str)-> bool{
+        matches!(self.peek(), Some(Token::Word(w)) if w.as_literal().as_deref() == Some(word))
+    }
+
+    fn at_closing_word(&self)-> bool{
+        CLOSING_WORDS.iter().any(|word| self.at_reserved(word))
+    }
+
+    /// Whether the next token ends a command list: the end of the input, a
+    /// closing reserved word, the `)` of a subshell or the terminator of a
+    /// `case` item.
+    fn at_list_end(&self)-> bool{
+        self.peek().is_none() || self.at_closing_word()
+            || matches!(self.peek(), Some(Token::Operator(Operator::RParen | Operator::DSemi | Operator::SemiAnd | Operator::DSemiAnd)))
+    }
+
+    /// Consumes the reserved word `word`, which the grammar requires here.
+    fn expect_reserved(&mut self, word: &str)-> Result<(), ParseError>{
+        match self.peek() {
+            _ if self.at_reserved(word) => {
+                self.pos += 1;
+                Ok(())
             },
-            '"' =>{
-                if in_single{
-                    arg.push(c);
-                }else{
-                    in_double = !in_double;
-                }
+            Some(token) => Err(unexpected(token)),
+            None => Err(end_of_input()),
+        }
+    }
+
+    /// Parses and-or lists separated by `;` or newlines, up to the end of
+    /// the input or a reserved word that ends the enclosing compound command.
+    fn parse_list(&mut self)-> Result<Program, ParseError>{
+        let mut lists = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.at_list_end() {
+                break;
+            }
+            let mut list = self.parse_and_or()?;
+            if matches!(self.peek(), Some(Token::Operator(Operator::Amp))) {
+                self.pos += 1;
+                list.background = true;
+                lists.push(list);
+                continue;
+            }
+            lists.push(list);
+            match self.peek() {
+                _ if self.at_list_end() => break,
+                None | Some(Token::Newline) => {},
+                Some(Token::Operator(Operator::Semi)) => { self.pos += 1; },
+                Some(token) => return Err(unexpected(token)),
+            }
+        }
+        Ok(Program { lists })
+    }
+
+    /// Parses the list inside a compound command, which may not be empty.
+    fn parse_compound_list(&mut self)-> Result<Program, ParseError>{
+        let program = self.parse_list()?;
+        if program.lists.is_empty() {
+            return Err(match self.peek() {
+                Some(token) => unexpected(token),
+                None => end_of_input(),
+            });
+        }
+        Ok(program)
+    }
+
+    fn parse_and_or(&mut self)-> Result<AndOrList, ParseError>{
+        let first = self.parse_pipeline()?;
+        let mut rest = Vec::new();
+        loop {
+            let op = match self.peek() {
+                Some(Token::Operator(Operator::AndIf)) => AndOrOp::And,
+                Some(Token::Operator(Operator::OrIf)) => AndOrOp::Or,
+                _ => break,
+            };
+            self.pos += 1;
+            self.skip_newlines();
+            rest.push((op, self.parse_pipeline()?));
+        }
+        Ok(AndOrList { first, rest, background: false })
+    }
+
+    fn parse_pipeline(&mut self)-> Result<Pipeline, ParseError>{
+        let start = self.pos;
+        let negated = matches!(self.peek(), Some(Token::Word(word)) if word.as_literal().as_deref() == Some("!"));
+        if negated {
+            self.pos += 1;
+        }
+        let mut commands = vec![self.parse_command()?];
+        while matches!(self.peek(), Some(Token::Operator(Operator::Pipe))) {
+            self.pos += 1;
+            self.skip_newlines();
+            commands.push(self.parse_command()?);
+        }
+        let text = self.source[self.spans[start].start..self.spans[self.pos - 1].end].iter().collect();
+        Ok(Pipeline { negated, commands, text })
+    }
+
+    fn parse_command(&mut self)-> Result<Command, ParseError>{
+        if self.at_reserved("function") {
+            self.pos += 1;
+            return self.parse_function();
+        }
+        if matches!(self.tokens.get(self.pos + 1), Some(Token::Operator(Operator::LParen)))
+            && matches!(self.peek(), Some(Token::Word(word)) if word.as_literal().is_some()) && !self.at_reserved("[[") {
+            return self.parse_function();
+        }
+        let compound = match self.peek() {
+            Some(Token::Arithmetic(expression)) => {
+                let expression = expression.clone();
+                self.pos += 1;
+                Some(CompoundCommand::Arithmetic(expression))
             },
-            '\''=>{
-                if in_double{
-                    arg.push(c);
-                }else{
-                    in_single = !in_single;
+            Some(Token::Operator(Operator::LParen)) => {
+                self.pos += 1;
+                let body = self.parse_compound_list()?;
+                match self.next() {
+                    Some(Token::Operator(Operator::RParen)) => {},
+                    Some(token) => return Err(unexpected(&token)),
+                    None => return Err(end_of_input()),
                 }
+                Some(CompoundCommand::Subshell(body))
             },
-            '\\' => {
-                if in_single{
-                    arg.push(c);
-                }else if in_double{
-                    if let Some(&next) = chars.peek(){
-                        if next == '"' || next == '\\' || next == '$' || next == '`' {
-                            chars.next();
-                            arg.push(next);
-                        }else{
-                            arg.push(c);
-                        }
-                    }
-                }else{
-                    if let Some(next) = chars.next(){
-                        arg.push(next);
+            Some(Token::Word(word)) => match word.as_literal().as_deref() {
+                Some("{") => {
+                    self.pos += 1;
+                    let body = self.parse_compound_list()?;
+                    self.expect_reserved("}")?;
+                    Some(CompoundCommand::BraceGroup(body))
+                },
+                Some("if") => Some(self.parse_if()?),
+                Some(keyword @ ("while" | "until")) => {
+                    let until = keyword == "until";
+                    self.pos += 1;
+                    let condition = self.parse_compound_list()?;
+                    let body = self.parse_do_group()?;
+                    Some(CompoundCommand::While { until, condition, body })
+                },
+                Some("for") => Some(self.parse_for()?),
+                Some("case") => Some(self.parse_case()?),
+                Some("[[") => Some(self.parse_conditional()?),
+                _ if self.at_closing_word() => return Err(unexpected(&Token::Word(word.clone()))),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(compound) = compound {
+            return Ok(Command::Compound(compound, self.parse_redirects()?));
+        }
+        let mut command = SimpleCommand { assignments: Vec::new(), words: Vec::new(), redirects: Vec::new() };
+        loop {
+            match self.peek() {
+                Some(Token::Word(word)) => {
+                    let word = word.clone();
+                    self.pos += 1;
+                    match split_assignment(&word) {
+                        Some(assignment) if command.words.is_empty() => command.assignments.push(assignment),
+                        _ => command.words.push(word),
                     }
-                }
+                },
+                _ if self.at_redirect() => command.redirects.push(self.parse_redirect()?),
+                _ => break,
+            }
+        }
+        if command.assignments.is_empty() && command.words.is_empty() && command.redirects.is_empty() {
+            return Err(match self.peek() {
+                Some(token) => unexpected(token),
+                None => end_of_input(),
+            });
+        }
+        Ok(Command::Simple(command))
+    }
+
+    /// Parses the rest of a function definition from its name: `name()` or,
+    /// after the `function` keyword, `name` with optional parentheses,
+    /// then a compound command as the body.
+    fn parse_function(&mut self)-> Result<Command, ParseError>{
+        let name = match self.next() {
+            Some(Token::Word(word)) => match word.as_literal() {
+                Some(name) if !RESERVED_WORDS.contains(&name.as_str()) => name,
+                _ => return Err(ParseError::Syntax(format!("`{}': not a valid identifier", word_text(&word)))),
             },
-            '>' if !in_single && !in_double && !is_error_file_name && !is_output_file_name => {
-                if !arg.is_empty(){
-                    args.push(arg);
-                    arg = String::new();
-                }
-                redirect_as_output = true;
-                is_output_file_name = true;
-                if chars.peek() == Some(&'>'){
-                    append_as_output = true;
-                    redirect_as_output = false;
-                    chars.next();
-                }
+            Some(token) => return Err(unexpected(&token)),
+            None => return Err(end_of_input()),
+        };
+        if matches!(self.peek(), Some(Token::Operator(Operator::LParen))) {
+            self.pos += 1;
+            match self.next() {
+                Some(Token::Operator(Operator::RParen)) => {},
+                Some(token) => return Err(unexpected(&token)),
+                None => return Err(end_of_input()),
+            }
+        }
+        self.skip_newlines();
+        let at_compound = COMPOUND_WORDS.iter().any(|word| self.at_reserved(word))
+            || matches!(self.peek(), Some(Token::Arithmetic(_) | Token::Operator(Operator::LParen)));
+        if !at_compound {
+            return Err(match self.peek() {
+                Some(token) => unexpected(token),
+                None => end_of_input(),
+            });
+        }
+        let body = self.parse_command()?;
+        Ok(Command::Function(name, Box::new(body)))
+    }
+
+    /// Parses `if list; then list; [elif list; then list;]... [else list;] fi`.
+    fn parse_if(&mut self)-> Result<CompoundCommand, ParseError>{
+        self.pos += 1;
+        let mut branches = Vec::new();
+        let mut else_body = None;
+        loop {
+            let condition = self.parse_compound_list()?;
+            self.expect_reserved("then")?;
+            branches.push((condition, self.parse_compound_list()?));
+            if self.at_reserved("elif") {
+                self.pos += 1;
+                continue;
+            }
+            if self.at_reserved("else") {
+                self.pos += 1;
+                else_body = Some(self.parse_compound_list()?);
+            }
+            self.expect_reserved("fi")?;
+            return Ok(CompoundCommand::If { branches, else_body });
+        }
+    }
+
+    /// Parses `for name [in words]; do list; done` and
+    /// `for ((init; condition; step)); do list; done`.
+    fn parse_for(&mut self)-> Result<CompoundCommand, ParseError>{
+        self.pos += 1;
+        if let Some(Token::Arithmetic(expressions)) = self.peek() {
+            let Some([init, condition, step]) = split_expressions(expressions) else {
+                return Err(ParseError::Syntax("syntax error: arithmetic expression required".to_string()));
+            };
+            self.pos += 1;
+            if matches!(self.peek(), Some(Token::Operator(Operator::Semi))) {
+                self.pos += 1;
+            }
+            let body = self.parse_do_group()?;
+            return Ok(CompoundCommand::ArithmeticFor { init, condition, step, body });
+        }
+        let name = match self.next() {
+            Some(Token::Word(word)) => match word.as_literal() {
+                Some(name) if is_name(&name) => name,
+                _ => return Err(ParseError::Syntax(format!("`{}': not a valid identifier", word_text(&word)))),
             },
-            '|' if !in_single && !in_double =>{
-                if !arg.is_empty(){
-                    args.push(arg);
-                    arg = String::new();
-                }
-                if !args.is_empty(){
-                    commands.push(args);
-                    args = Vec::new();
-                }
+            Some(token) => return Err(unexpected(&token)),
+            None => return Err(end_of_input()),
+        };
+        self.skip_newlines();
+        let words = if self.at_reserved("in") {
+            self.pos += 1;
+            let mut words = Vec::new();
+            while let Some(Token::Word(word)) = self.peek() {
+                words.push(word.clone());
+                self.pos += 1;
+            }
+            match self.next() {
+                Some(Token::Operator(Operator::Semi) | Token::Newline) => {},
+                Some(token) => return Err(unexpected(&token)),
+                None => return Err(end_of_input()),
+            }
+            words
+        }else{
+            if matches!(self.peek(), Some(Token::Operator(Operator::Semi))) {
+                self.pos += 1;
+            }
+            let all = ParamExpansion { name: "@".to_string(), index: None, op: ParamOp::Value };
+            vec![Word { parts: vec![WordPart::DoubleQuoted(vec![WordPart::Parameter(all)])] }]
+        };
+        let body = self.parse_do_group()?;
+        Ok(CompoundCommand::For { name, words, body })
+    }
+
+    /// Parses `case word in [(]pattern[|pattern]...) list;; ... esac`, where
+    /// an item may also end with `;&` or `;;&`, and the last one with `esac`.
+    fn parse_case(&mut self)-> Result<CompoundCommand, ParseError>{
+        self.pos += 1;
+        let word = self.parse_word()?;
+        self.skip_newlines();
+        self.expect_reserved("in")?;
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.at_reserved("esac") {
+                self.pos += 1;
+                return Ok(CompoundCommand::Case { word, items });
+            }
+            if matches!(self.peek(), Some(Token::Operator(Operator::LParen))) {
+                self.pos += 1;
+            }
+            let mut patterns = vec![self.parse_word()?];
+            while matches!(self.peek(), Some(Token::Operator(Operator::Pipe))) {
+                self.pos += 1;
+                patterns.push(self.parse_word()?);
+            }
+            match self.next() {
+                Some(Token::Operator(Operator::RParen)) => {},
+                Some(token) => return Err(unexpected(&token)),
+                None => return Err(end_of_input()),
+            }
+            let body = self.parse_list()?;
+            let terminator = match self.peek() {
+                Some(Token::Operator(Operator::DSemi)) => CaseTerminator::Break,
+                Some(Token::Operator(Operator::SemiAnd)) => CaseTerminator::FallThrough,
+                Some(Token::Operator(Operator::DSemiAnd)) => CaseTerminator::Continue,
+                _ if self.at_reserved("esac") => CaseTerminator::Break,
+                Some(token) => return Err(unexpected(token)),
+                None => return Err(end_of_input()),
+            };
+            if !self.at_reserved("esac") {
+                self.pos += 1;
+            }
+            items.push(CaseItem { patterns, body, terminator });
+        }
+    }
+
+    /// Parses `[[ expression ]]`, in which `!`, `&&`, `||` and parentheses
+    /// combine unary and binary tests.
+    fn parse_conditional(&mut self)-> Result<CompoundCommand, ParseError>{
+        self.pos += 1;
+        let expression = self.parse_conditional_or()?;
+        self.skip_newlines();
+        self.expect_reserved("]]")?;
+        Ok(CompoundCommand::Conditional(expression))
+    }
+
+    fn parse_conditional_or(&mut self)-> Result<ConditionalExpr, ParseError>{
+        let mut expression = self.parse_conditional_and()?;
+        while matches!(self.peek(), Some(Token::Operator(Operator::OrIf))) {
+            self.pos += 1;
+            let right = self.parse_conditional_and()?;
+            expression = ConditionalExpr::Or(Box::new(expression), Box::new(right));
+        }
+        Ok(expression)
+    }
+
+    fn parse_conditional_and(&mut self)-> Result<ConditionalExpr, ParseError>{
+        let mut expression = self.parse_conditional_not()?;
+        while matches!(self.peek(), Some(Token::Operator(Operator::AndIf))) {
+            self.pos += 1;
+            let right = self.parse_conditional_not()?;
+            expression = ConditionalExpr::And(Box::new(expression), Box::new(right));
+        }
+        Ok(expression)
+    }
+
+    fn parse_conditional_not(&mut self)-> Result<ConditionalExpr, ParseError>{
+        self.skip_newlines();
+        if self.at_reserved("!") {
+            self.pos += 1;
+            return Ok(ConditionalExpr::Not(Box::new(self.parse_conditional_not()?)));
+        }
+        if matches!(self.peek(), Some(Token::Operator(Operator::LParen))) {
+            self.pos += 1;
+            let expression = self.parse_conditional_or()?;
+            self.skip_newlines();
+            return match self.next() {
+                Some(Token::Operator(Operator::RParen)) => Ok(expression),
+                Some(token) => Err(unexpected(&token)),
+                None => Err(end_of_input()),
+            };
+        }
+        let word = self.parse_conditional_word()?;
+        let literal = word.as_literal();
+        if let Some(op) = literal.filter(|op| UNARY_OPERATORS.contains(&op.as_str())) {
+            if self.at_reserved("]]") {
+                return Err(ParseError::Syntax("unexpected argument `]]' to conditional unary operator".to_string()));
+            }
+            return Ok(ConditionalExpr::Unary(op, self.parse_conditional_word()?));
+        }
+        let op = match self.peek() {
+            Some(Token::Operator(Operator::Less)) => "<".to_string(),
+            Some(Token::Operator(Operator::Great)) => ">".to_string(),
+            Some(Token::Word(op)) => match op.as_literal() {
+                Some(op) if BINARY_OPERATORS.contains(&op.as_str()) || op == "=~" => op,
+                _ => return Ok(ConditionalExpr::Word(word)),
             },
-            '1' if !in_single && !in_double=>{
-                if chars.peek() == Some(&'>'){
-                    chars.next();
-                    redirect_as_output = true;
-                    is_output_file_name = true;
-                    if !arg.is_empty(){
-                        args.push(arg);
-                        arg = String::new();
-                    }
-                    if chars.peek() == Some(&'>'){
-                        append_as_output = true;
-                        redirect_as_output = false;
-                        chars.next();
+            _ => return Ok(ConditionalExpr::Word(word)),
+        };
+        self.pos += 1;
+        let right = if op == "=~" { self.parse_regex_word()? } else { self.parse_conditional_word()? };
+        Ok(ConditionalExpr::Binary(word, op, right))
+    }
+
+    /// Consumes an operand of a conditional expression, which `]]` cannot be.
+    fn parse_conditional_word(&mut self)-> Result<Word, ParseError>{
+        if self.at_reserved("]]") {
+            return Err(ParseError::Syntax("syntax error in conditional expression".to_string()));
+        }
+        self.parse_word()
+    }
+
+    /// Reads the regular expression after `=~`, where `(`, `)`, `|`, `<` and
+    /// `>` are ordinary characters: tokens written without blanks between
+    /// them make up a single word.
+    fn parse_regex_word(&mut self)-> Result<Word, ParseError>{
+        let mut parts = Vec::new();
+        let mut depth = 0;
+        let mut end = None;
+        while let Some(token) = self.peek() {
+            let span = self.spans[self.pos].clone();
+            if end.is_some_and(|end| span.start != end) {
+                break;
+            }
+            match token {
+                Token::Word(word) if word.as_literal().as_deref() != Some("]]") => parts.extend(word.parts.iter().cloned()),
+                Token::Operator(Operator::RParen) if depth == 0 => break,
+                Token::Operator(op @ (Operator::LParen | Operator::RParen | Operator::Pipe | Operator::Less | Operator::Great)) => {
+                    match op {
+                        Operator::LParen => depth += 1,
+                        Operator::RParen => depth -= 1,
+                        _ => {},
                     }
-                }else{
-                    arg.push(c);
-                }
-            },
-            '2' if !in_single && !in_double=>{
-                if chars.peek() == Some(&'>'){
-                    chars.next();
-                    redirect_as_error = true;
-                    is_error_file_name = true;
-                    if !arg.is_empty(){
-                        args.push(arg);
-                        arg = String::new();
+                    parts.push(WordPart::Literal(self.source[span.clone()].iter().collect()));
+                },
+                _ => break,
+            }
+            end = Some(span.end);
+            self.pos += 1;
+        }
+        if parts.is_empty() {
+            return Err(ParseError::Syntax("syntax error in conditional expression".to_string()));
+        }
+        Ok(Word { parts })
+    }
+
+    /// Consumes a word the grammar requires here, such as a `case` pattern.
+    fn parse_word(&mut self)-> Result<Word, ParseError>{
+        match self.next() {
+            Some(Token::Word(word)) => Ok(word),
+            Some(token) => Err(unexpected(&token)),
+            None => Err(end_of_input()),
+        }
+    }
+
+    /// Parses `do list; done`, possibly on a later line.
+    fn parse_do_group(&mut self)-> Result<Program, ParseError>{
+        self.skip_newlines();
+        self.expect_reserved("do")?;
+        let body = self.parse_compound_list()?;
+        self.expect_reserved("done")?;
+        Ok(body)
+    }
+
+    /// Parses the redirections after a compound command.
+    fn parse_redirects(&mut self)-> Result<Vec<Redirect>, ParseError>{
+        let mut redirects = Vec::new();
+        while self.at_redirect() {
+            redirects.push(self.parse_redirect()?);
+        }
+        Ok(redirects)
+    }
+
+    fn at_redirect(&self)-> bool{
+        matches!(self.peek(), Some(Token::IoNumber(_)) | Some(Token::Operator(Operator::Less | Operator::DLess | Operator::DLessDash
+            | Operator::TLess | Operator::Great | Operator::DGreat | Operator::Clobber | Operator::LessAnd
+            | Operator::GreatAnd | Operator::LessGreat | Operator::AndGreat | Operator::AndDGreat)))
+    }
+
+    fn parse_redirect(&mut self)-> Result<Redirect, ParseError>{
+        let mut fd = None;
+        if let Some(Token::IoNumber(number)) = self.peek() {
+            fd = Some(*number);
+            self.pos += 1;
+        }
+        let op = match self.next() {
+            Some(Token::Operator(Operator::Less)) => RedirectOp::Input,
+            Some(Token::Operator(Operator::DLess | Operator::DLessDash)) => RedirectOp::HereDoc,
+            Some(Token::Operator(Operator::TLess)) => RedirectOp::HereString,
+            Some(Token::Operator(Operator::Great)) => RedirectOp::Output,
+            Some(Token::Operator(Operator::DGreat)) => RedirectOp::Append,
+            Some(Token::Operator(Operator::Clobber)) => RedirectOp::Clobber,
+            Some(Token::Operator(Operator::LessGreat)) => RedirectOp::ReadWrite,
+            Some(Token::Operator(Operator::LessAnd)) => RedirectOp::DupInput,
+            Some(Token::Operator(Operator::GreatAnd)) => RedirectOp::DupOutput,
+            Some(Token::Operator(Operator::AndGreat)) if fd.is_none() => RedirectOp::OutputAndError,
+            Some(Token::Operator(Operator::AndDGreat)) if fd.is_none() => RedirectOp::AppendAndError,
+            Some(token) => return Err(unexpected(&token)),
+            None => return Err(unexpected(&Token::Newline)),
+        };
+        let default_fd = match op {
+            RedirectOp::Input | RedirectOp::HereDoc | RedirectOp::HereString | RedirectOp::ReadWrite | RedirectOp::DupInput => 0,
+            RedirectOp::Output | RedirectOp::Clobber | RedirectOp::Append | RedirectOp::DupOutput
+                | RedirectOp::OutputAndError | RedirectOp::AppendAndError => 1,
+        };
+        match self.next() {
+            Some(Token::Word(target)) => Ok(Redirect { fd: fd.unwrap_or(default_fd), op, target }),
+            Some(token) => Err(unexpected(&token)),
+            None => Err(unexpected(&Token::Newline)),
+        }
+    }
+}
+
+/// Splits `NAME=value` into an assignment when `NAME` is a valid,
+/// unquoted variable name.
+fn split_assignment(word: &Word)-> Option<Assignment>{
+    let WordPart::Literal(first) = word.parts.first()? else {
+        return None;
+    };
+    let (name, value) = first.split_once('=')?;
+    if !is_name(name) {
+        return None;
+    }
+    let mut parts = Vec::new();
+    if !value.is_empty() {
+        parts.push(WordPart::Literal(value.to_string()));
+    }
+    parts.extend(word.parts[1..].iter().cloned());
+    Some(Assignment { name: name.to_string(), value: Word { parts } })
+}
+
+/// Whether `s` is a valid variable name.
+pub fn is_name(s: &str)-> bool{
+    let mut chars = s.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits the text of `for ((...))` at its top-level semicolons into the
+/// three expressions, or `None` if there are not exactly three.
+fn split_expressions(word: &Word)-> Option<[Word; 3]>{
+    let mut expressions = vec![Word::default()];
+    for part in &word.parts {
+        match part {
+            WordPart::Literal(text) => {
+                for (i, piece) in text.split(';').enumerate() {
+                    if i > 0 {
+                        expressions.push(Word::default());
                     }
-                    if chars.peek() == Some(&'>'){
-                        append_as_error = true;
-                        redirect_as_error = false;
-                        chars.next();
+                    if !piece.is_empty() {
+                        expressions.last_mut()?.parts.push(WordPart::Literal(piece.to_string()));
                     }
-                }else{
-                    arg.push(c);
                 }
-            }
-            _ => arg.push(c),
+            },
+            part => expressions.last_mut()?.parts.push(part.clone()),
         }
     }
-    if !arg.is_empty() {
-        if is_output_file_name{
-            output_file.push(arg);
-        }else if is_error_file_name{
-            error_file.push(arg);
-        }else{
-            args.push(arg);
-        }
+    expressions.try_into().ok()
+}
+
+/// The source text of a word, for error messages, when it is a literal.
+fn word_text(word: &Word)-> String{
+    word.as_literal().unwrap_or_else(|| "word".to_string())
+}
+
+fn end_of_input()-> ParseError{
+    ParseError::Incomplete("syntax error: unexpected end of file".to_string())
+}
+
+fn unexpected(token: &Token)-> ParseError{
+    let word;
+    let text = match token {
+        Token::Word(w) => {
+            word = word_text(w);
+            word.as_str()
+        },
+        Token::IoNumber(_) => "number",
+        Token::Newline => "newline",
+        Token::Arithmetic(_) => "((",
+        Token::Operator(Operator::Pipe) => "|",
+        Token::Operator(Operator::AndIf) => "&&",
+        Token::Operator(Operator::OrIf) => "||",
+        Token::Operator(Operator::Semi) => ";",
+        Token::Operator(Operator::Less) => "<",
+        Token::Operator(Operator::DLess) => "<<",
+        Token::Operator(Operator::DLessDash) => "<<-",
+        Token::Operator(Operator::TLess) => "<<<",
+        Token::Operator(Operator::Great) => ">",
+        Token::Operator(Operator::DGreat) => ">>",
+        Token::Operator(Operator::Clobber) => ">|",
+        Token::Operator(Operator::LessAnd) => "<&",
+        Token::Operator(Operator::GreatAnd) => ">&",
+        Token::Operator(Operator::LessGreat) => "<>",
+        Token::Operator(Operator::AndGreat) => "&>",
+        Token::Operator(Operator::AndDGreat) => "&>>",
+        Token::Operator(Operator::Amp) => "&",
+        Token::Operator(Operator::LParen) => "(",
+        Token::Operator(Operator::RParen) => ")",
+        Token::Operator(Operator::DSemi) => ";;",
+        Token::Operator(Operator::SemiAnd) => ";&",
+        Token::Operator(Operator::DSemiAnd) => ";;&",
+    };
+    ParseError::Syntax(format!("syntax error near unexpected token `{}'", text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn is_syntax_error(input: &str)-> bool{
+        matches!(parse_program(input), Err(ParseError::Syntax(_)))
+    }
+
+    #[test]
+    fn parses_pipelines_and_redirections(){
+        let program = parse_program("x=1 a 2>f | b >>g y=2").unwrap();
+        let commands = &program.lists[0].first.commands;
+        assert_eq!(commands.len(), 2);
+        assert!(matches!(&commands[0], Command::Simple(command)
+            if command.assignments.len() == 1 && command.words.len() == 1
+                && command.redirects[0].fd == 2 && command.redirects[0].op == RedirectOp::Output));
+        assert!(matches!(&commands[1], Command::Simple(command)
+            if command.assignments.is_empty() && command.words.len() == 2
+                && command.redirects[0].fd == 1 && command.redirects[0].op == RedirectOp::Append));
+    }
+
+    #[test]
+    fn rejects_misplaced_pipes_and_redirections(){
+        assert!(is_syntax_error("| a"));
+        assert!(is_syntax_error("a | | b"));
+        assert!(is_syntax_error("a >"));
     }
-    if !args.is_empty(){
-        commands.push(args.clone());
+
+    #[test]
+    fn builds_lists_and_pipelines(){
+        let program = parse_program("a | b && ! c || d; e").unwrap();
+        assert_eq!(program.lists.len(), 2);
+        let list = &program.lists[0];
+        assert_eq!(list.first.commands.len(), 2);
+        assert_eq!(list.rest.iter().map(|(op, _)| *op).collect::<Vec<_>>(), [AndOrOp::And, AndOrOp::Or]);
+        assert!(list.rest[0].1.negated);
+        assert!(!list.first.negated);
+    }
+
+    #[test]
+    fn rejects_misplaced_list_operators(){
+        assert!(is_syntax_error("; a"));
+        assert!(is_syntax_error("a && && b"));
+        assert!(is_syntax_error("a ;; b"));
+    }
+
+    fn is_incomplete(input: &str)-> bool{
+        matches!(parse_program(input), Err(ParseError::Incomplete(_)))
+    }
+
+    #[test]
+    fn waits_for_the_rest_of_a_list(){
+        assert!(is_incomplete("a &&"));
+        assert!(is_incomplete("a ||"));
+        assert!(is_incomplete("a |"));
+        assert!(parse_program("a &&\nb").is_ok());
+    }
+
+    #[test]
+    fn waits_for_the_end_of_compound_commands(){
+        assert!(is_incomplete("if true; then echo"));
+        assert!(is_incomplete("while true; do"));
+        assert!(is_incomplete("for x in a b"));
+        assert!(parse_program("if true\nthen echo\nfi").is_ok());
+    }
+
+    #[test]
+    fn rejects_misplaced_reserved_words(){
+        assert!(is_syntax_error("fi"));
+        assert!(is_syntax_error("if true; fi"));
+        assert!(is_syntax_error("while true; done"));
+    }
+
+    #[test]
+    fn parses_case_items(){
+        assert!(is_incomplete("case x in x)"));
+        let program = parse_program("case $x in a|b) echo;; (c) ;& *) ;;& esac").unwrap();
+        assert!(matches!(&program.lists[0].first.commands[0], Command::Compound(CompoundCommand::Case { items, .. }, _)
+            if items.len() == 3 && items[0].patterns.len() == 2));
+    }
+
+    #[test]
+    fn parses_function_definitions(){
+        assert!(is_incomplete("f() {"));
+        let program = parse_program("f() { echo; }").unwrap();
+        assert!(matches!(program.lists[0].first.commands[0], Command::Function(ref name, _) if name == "f"));
+        let program = parse_program("function g { echo; }").unwrap();
+        assert!(matches!(program.lists[0].first.commands[0], Command::Function(ref name, _) if name == "g"));
+    }
+
+    #[test]
+    fn runs_lists_ending_in_ampersand_in_the_background(){
+        let program = parse_program("a && b & c").unwrap();
+        assert!(program.lists[0].background);
+        assert!(!program.lists[1].background);
+    }
+
+    #[test]
+    fn parses_conditional_expressions(){
+        assert!(is_incomplete("[[ a == b"));
+        assert!(is_syntax_error("[[ -f ]]"));
+        let program = parse_program("[[ ( a == b || -n c ) && ! x =~ ^(y|z)$ ]]").unwrap();
+        let Command::Compound(CompoundCommand::Conditional(expression), _) = &program.lists[0].first.commands[0] else {
+            panic!("not a conditional");
+        };
+        let ConditionalExpr::And(left, right) = expression else {
+            panic!("not an and");
+        };
+        assert!(matches!(**left, ConditionalExpr::Or(..)));
+        assert!(matches!(&**right, ConditionalExpr::Not(inner)
+            if matches!(&**inner, ConditionalExpr::Binary(_, op, regex) if op == "=~" && regex.as_literal().as_deref() == Some("^(y|z)$"))));
     }
-    ParsedResult { commands, output_file, error_file, redirect_as_output, redirect_as_error, append_as_error, append_as_output }
-}
\ No newline at end of file
+}
@@ -0,0 +1,228 @@
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+use crate::ast::{Redirect, RedirectOp};
+use crate::expand::expand_word;
+use crate::shell::Shell;
+
+/// Lowest descriptor used for files the shell opens itself, so they never
+/// collide with the small descriptors a redirection targets. A command that
+/// redirects higher descriptors moves them above those; see `first_private_fd`.
+const FIRST_PRIVATE_FD: i32 = 10;
+
+/// What a redirection makes a file descriptor refer to.
+enum FdSource{
+    /// A file opened for the redirection.
+    File(OwnedFd),
+    /// A copy of another descriptor, as made by `n>&m`.
+    Dup(i32),
+    /// Nothing: `n>&-` closes the descriptor.
+    Close
+}
+
+/// The redirections of a command, opened and ready to be applied in the
+/// order they were written.
+pub struct FdPlan{
+    ops: Vec<(i32, FdSource)>
+}
+
+/// Expands the targets of a command's redirections and opens the files they
+/// name. Nothing is applied yet; see `FdPlan::apply` and `FdPlan::apply_in_shell`.
+///
+/// # Arguments
+/// * `redirects` - The redirections as parsed, in source order
+/// * `shell` - Shell state targets are expanded with
+///
+/// # Returns
+/// * `io::Result<FdPlan>` - The operations to apply, or the first failure
+pub fn open_redirections(redirects: &[Redirect], shell: &mut Shell)-> io::Result<FdPlan>{
+    let mut ops = Vec::new();
+    let first_private = first_private_fd(redirects.iter().map(|redirect| redirect.fd));
+    for redirect in redirects {
+        let target = expand_word(&redirect.target, shell).map_err(io::Error::other)?;
+        let mut options = OpenOptions::new();
+        let file = match redirect.op {
+            RedirectOp::DupInput | RedirectOp::DupOutput => {
+                if target == "-" {
+                    ops.push((redirect.fd, FdSource::Close));
+                    continue;
+                }
+                if let Ok(source) = target.parse::<i32>() {
+                    if !is_open_after(&ops, source) {
+                        return Err(io::Error::other(format!("{}: Bad file descriptor", source)));
+                    }
+                    ops.push((redirect.fd, FdSource::Dup(source)));
+                    continue;
+                }
+                if redirect.op == RedirectOp::DupInput {
+                    return Err(io::Error::other(format!("{}: ambiguous redirect", target)));
+                }
+                // `>&file` is the older spelling of `&>file`.
+                let file = options.create(true).write(true).truncate(true).open(&target);
+                let file = private_fd(file, &target, first_private)?;
+                ops.push((1, FdSource::File(file)));
+                ops.push((2, FdSource::Dup(1)));
+                continue;
+            },
+            RedirectOp::HereDoc => here_document_file(&target),
+            RedirectOp::HereString => here_document_file(&format!("{}\n", target)),
+            RedirectOp::Input => options.read(true).open(&target),
+            RedirectOp::ReadWrite => options.read(true).write(true).create(true).open(&target),
+            RedirectOp::Output | RedirectOp::OutputAndError if shell.options.noclobber => create_new_file(&target),
+            RedirectOp::Output | RedirectOp::OutputAndError | RedirectOp::Clobber => options.create(true).write(true).truncate(true).open(&target),
+            RedirectOp::Append | RedirectOp::AppendAndError => options.create(true).append(true).open(&target),
+        };
+        let file = private_fd(file, &target, first_private)?;
+        ops.push((redirect.fd, FdSource::File(file)));
+        if matches!(redirect.op, RedirectOp::OutputAndError | RedirectOp::AppendAndError) {
+            ops.push((2, FdSource::Dup(redirect.fd)));
+        }
+    }
+    Ok(FdPlan { ops })
+}
+
+/// Whether `fd` will be open once the operations so far have been applied.
+fn is_open_after(ops: &[(i32, FdSource)], fd: i32)-> bool{
+    match ops.iter().rev().find(|(target, _)| *target == fd) {
+        Some((_, FdSource::Close)) => false,
+        Some(_) => true,
+        None => (unsafe { libc::fcntl(fd, libc::F_GETFD) }) != -1,
+    }
+}
+
+/// The lowest descriptor the shell may keep its own files on while applying
+/// redirections of `targets`: above all of them, so that none is taken
+/// before it is redirected.
+fn first_private_fd(targets: impl Iterator<Item = i32>)-> i32{
+    targets.map(|fd| fd + 1).fold(FIRST_PRIVATE_FD, i32::max)
+}
+
+/// Moves an opened redirection target out of the range of descriptors that
+/// the command's redirections name, prefixing errors with the target.
+fn private_fd(file: io::Result<File>, target: &str, first_private: i32)-> io::Result<OwnedFd>{
+    let file = file.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", target, e)))?;
+    let fd = unsafe { libc::fcntl(file.as_raw_fd(), libc::F_DUPFD_CLOEXEC, first_private) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Opens the target of `>` under `set -C`: an existing regular file is not
+/// overwritten, while other files such as `/dev/null` are opened as usual.
+fn create_new_file(target: &str)-> io::Result<File>{
+    match fs::metadata(target) {
+        Ok(metadata) if metadata.is_file() => Err(io::Error::new(io::ErrorKind::AlreadyExists, "cannot overwrite existing file")),
+        Ok(_) => OpenOptions::new().write(true).open(target),
+        Err(_) => OpenOptions::new().write(true).create_new(true).open(target),
+    }
+}
+
+/// Stores the text fed to a command by a here-document or here-string in
+/// an unlinked temporary file, positioned at its start.
+fn here_document_file(content: &str)-> io::Result<File>{
+    let mut attempt = 0;
+    loop {
+        let path = env::temp_dir().join(format!("rust-shell-heredoc-{}-{}", std::process::id(), attempt));
+        match OpenOptions::new().read(true).write(true).create_new(true).open(&path) {
+            Ok(mut file) => {
+                let _ = fs::remove_file(&path);
+                file.write_all(content.as_bytes())?;
+                file.seek(SeekFrom::Start(0))?;
+                return Ok(file);
+            },
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => attempt += 1,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl FdPlan{
+    /// Applies the redirections to the current process for good. Only uses
+    /// `dup2`, `fcntl` and `close`, so it is safe to call between `fork`
+    /// and `exec`.
+    pub fn apply(&self)-> io::Result<()>{
+        for (fd, source) in &self.ops {
+            let result = match source {
+                FdSource::File(file) => unsafe { libc::dup2(file.as_raw_fd(), *fd) },
+                FdSource::Dup(source) if source == fd => unsafe {
+                    libc::fcntl(*fd, libc::F_SETFD, 0)
+                },
+                FdSource::Dup(source) => unsafe { libc::dup2(*source, *fd) },
+                FdSource::Close => {
+                    unsafe { libc::close(*fd) };
+                    0
+                },
+            };
+            if result == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// Applies the redirections to the shell itself until the returned guard
+    /// is dropped, which puts the original descriptors back. Used for
+    /// commands that run inside the shell process, such as built-ins.
+    pub fn apply_in_shell(&self)-> io::Result<SavedFds>{
+        let _ = io::stdout().flush();
+        let mut saved = SavedFds { fds: Vec::new() };
+        let first_private = first_private_fd(self.ops.iter().map(|(fd, _)| *fd));
+        for (fd, _) in &self.ops {
+            if saved.fds.iter().any(|(target, _)| target == fd) {
+                continue;
+            }
+            let copy = unsafe { libc::fcntl(*fd, libc::F_DUPFD_CLOEXEC, first_private) };
+            saved.fds.push((*fd, copy));
+        }
+        self.apply()?;
+        Ok(saved)
+    }
+}
+
+/// Copies of the shell's descriptors taken before redirecting them; dropping
+/// this restores them.
+pub struct SavedFds{
+    /// Each redirected descriptor and its saved copy, or -1 if it was closed.
+    fds: Vec<(i32, i32)>
+}
+
+impl Drop for SavedFds{
+    fn drop(&mut self){
+        let _ = io::stdout().flush();
+        for (fd, copy) in self.fds.iter().rev() {
+            unsafe {
+                if *copy == -1 {
+                    libc::close(*fd);
+                }else{
+                    libc::dup2(*copy, *fd);
+                    libc::close(*copy);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_private_descriptors_above_redirected_ones(){
+        assert_eq!(first_private_fd([0, 1, 2].into_iter()), FIRST_PRIVATE_FD);
+        assert_eq!(first_private_fd([1, 10].into_iter()), 11);
+        assert_eq!(first_private_fd([12, 3].into_iter()), 13);
+    }
+
+    #[test]
+    fn opens_files_above_redirected_descriptors(){
+        let path = env::temp_dir().join(format!("rust-shell-redirect-test-{}", std::process::id()));
+        let fd = private_fd(File::create(&path), "f", 20).unwrap();
+        assert!(fd.as_raw_fd() >= 20);
+        let _ = fs::remove_file(&path);
+        let error = private_fd(File::open(&path), "missing", 20).unwrap_err();
+        assert!(error.to_string().starts_with("missing: "));
+    }
+}
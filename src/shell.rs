@@ -0,0 +1,273 @@
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use crate::HistoryAction;
+use crate::ast::Command;
+use crate::glob::GlobOptions;
+use crate::jobs::Jobs;
+use crate::signals;
+use crate::vars::{Variable, Variables};
+
+/// State the shell keeps between command lines.
+pub struct Shell{
+    /// Names of the commands the shell implements itself.
+    pub built_ins: Vec<String>,
+    /// Command history, refreshed before every command line.
+    pub history: Vec<String>,
+    /// History changes requested by `history -r/-w/-a`, carried out by the
+    /// REPL that owns the line editor.
+    pub history_actions: Vec<(Option<String>, HistoryAction)>,
+    /// Exit status of the most recent pipeline, exposed as `$?`.
+    pub last_status: i32,
+    /// Status of the last command substitution run while expanding the
+    /// current command, which becomes the status of a command without a name.
+    pub substitution_status: Option<i32>,
+    /// Shell and environment variables.
+    pub vars: Variables,
+    /// Globbing options set with `shopt`.
+    pub glob_options: GlobOptions,
+    /// Options set with `set`.
+    pub options: ShellOptions,
+    /// Set by `exit`; the shell terminates with `last_status`.
+    pub exit_requested: bool,
+    /// Number of loops currently running, which bounds `break` and `continue`.
+    pub loop_depth: usize,
+    /// A `break` or `continue` on its way out to the loop it applies to.
+    pub loop_control: Option<LoopControl>,
+    /// Functions by name; the body is shared so that a function can be
+    /// redefined while it runs.
+    pub functions: HashMap<String, Rc<Command>>,
+    /// `$$`: the process ID of the shell, which subshells keep.
+    pub pid: u32,
+    /// `$!`: the process ID of the most recent background command.
+    pub last_background_pid: Option<libc::pid_t>,
+    /// Whether commands are read from a terminal with a prompt.
+    pub interactive: bool,
+    /// Background and stopped jobs, and the terminal while job control is on.
+    pub jobs: Jobs,
+    /// `$0`: the name of the script or of the shell.
+    pub script_name: String,
+    /// `$1`, `$2`, ..., replaced for the duration of a function call.
+    pub positional_params: Vec<String>,
+    /// Names of the functions being run, innermost last, as in `FUNCNAME`.
+    pub call_stack: Vec<String>,
+    /// For every running function, the variables declared `local` in it and
+    /// what they were before, to restore on return.
+    pub local_scopes: Vec<Vec<(String, Option<Variable>)>>,
+    /// Set by `return`; the commands left in the function are skipped.
+    pub returning: bool,
+    /// Commands set with `trap`; an empty one ignores the signal.
+    pub traps: BTreeMap<TrapCondition, String>,
+    /// Whether a trap command is running, which keeps the `DEBUG` and `ERR`
+    /// traps from running for its own commands.
+    pub running_trap: bool,
+    /// How many conditions the running command is part of: those of `if`,
+    /// `while` and `until`, pipelines before the last `&&` or `||`, and ones
+    /// negated with `!`. Failures there do not run the `ERR` trap.
+    pub conditions: usize
+}
+
+/// What a trap is set on: a signal or one of the shell's own conditions.
+/// The order is the one `trap -p` lists them in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TrapCondition{
+    /// The shell or subshell exiting.
+    Exit,
+    Signal(i32),
+    /// Before every simple command.
+    Debug,
+    /// A command failing outside of a condition.
+    Err,
+    /// A function returning, for a trap set while it ran.
+    Return
+}
+
+impl TrapCondition{
+    /// Reads a condition as `trap` takes it: `EXIT` or 0, `DEBUG`, `ERR`,
+    /// `RETURN`, or a signal name or number.
+    pub fn parse(spec: &str)-> Option<Self>{
+        match spec.to_ascii_uppercase().as_str() {
+            "EXIT" | "0" => Some(TrapCondition::Exit),
+            "DEBUG" => Some(TrapCondition::Debug),
+            "ERR" => Some(TrapCondition::Err),
+            "RETURN" => Some(TrapCondition::Return),
+            _ => match spec.parse::<i32>() {
+                Ok(number) => signals::SIGNAL_NAMES.iter()
+                    .any(|(_, signal)| *signal == number)
+                    .then_some(TrapCondition::Signal(number)),
+                Err(_) => signals::signal_number(spec).map(TrapCondition::Signal),
+            },
+        }
+    }
+
+    /// The name `trap -p` shows, such as `EXIT` or `SIGINT`.
+    pub fn name(self)-> String{
+        match self {
+            TrapCondition::Exit => "EXIT".to_string(),
+            TrapCondition::Signal(signal) => signals::signal_name(signal),
+            TrapCondition::Debug => "DEBUG".to_string(),
+            TrapCondition::Err => "ERR".to_string(),
+            TrapCondition::Return => "RETURN".to_string(),
+        }
+    }
+}
+
+/// Options turned on with `set -x` or `set -o name` and off with `set +x`
+/// or `set +o name`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ShellOptions{
+    /// `-e`: exit when a command fails outside of a condition.
+    pub errexit: bool,
+    /// `-C`: `>` does not overwrite existing files.
+    pub noclobber: bool,
+    /// `-n`: read commands without running them, to check their syntax.
+    pub noexec: bool,
+    /// `-f`: no pathname expansion.
+    pub noglob: bool,
+    /// `-u`: expanding an unset parameter is an error.
+    pub nounset: bool,
+    /// A pipeline fails with the status of its last failing stage.
+    pub pipefail: bool,
+    /// `-x`: print commands to stderr before running them.
+    pub xtrace: bool
+}
+
+impl ShellOptions{
+    /// Option names in the order `set -o` lists them, with their letters.
+    pub const NAMES: [(&'static str, Option<char>); 7] = [
+        ("errexit", Some('e')), ("noclobber", Some('C')), ("noexec", Some('n')), ("noglob", Some('f')),
+        ("nounset", Some('u')), ("pipefail", None), ("xtrace", Some('x')),
+    ];
+
+    pub fn option_mut(&mut self, name: &str)-> Option<&mut bool>{
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "noclobber" => Some(&mut self.noclobber),
+            "noexec" => Some(&mut self.noexec),
+            "noglob" => Some(&mut self.noglob),
+            "nounset" => Some(&mut self.nounset),
+            "pipefail" => Some(&mut self.pipefail),
+            "xtrace" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+
+    /// The option a letter such as `e` in `set -e` stands for.
+    pub fn letter_mut(&mut self, letter: char)-> Option<&mut bool>{
+        let (name, _) = Self::NAMES.iter().find(|(_, other)| *other == Some(letter))?;
+        self.option_mut(name)
+    }
+}
+
+/// `break N` or `continue N`, with the number of enclosing loops still to
+/// leave.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LoopControl{
+    Break(usize),
+    Continue(usize)
+}
+
+impl Shell{
+    pub fn new()-> Self{
+        let built_ins = vec!["echo", "exit", "type", "pwd", "cd", "history", "shopt", "let", "export", "unset", "readonly", "break", "continue", "local", "return", "shift", "set", "jobs", "fg", "bg", "disown", "wait", "trap", "test", "["]
+            .into_iter()
+            .map(|s| s.to_string())
+            .collect();
+        let mut shell = Shell {
+            built_ins,
+            history: Vec::new(),
+            history_actions: Vec::new(),
+            last_status: 0,
+            substitution_status: None,
+            vars: Variables::from_env(),
+            glob_options: GlobOptions::default(),
+            options: ShellOptions::default(),
+            exit_requested: false,
+            loop_depth: 0,
+            loop_control: None,
+            functions: HashMap::new(),
+            pid: std::process::id(),
+            last_background_pid: None,
+            interactive: false,
+            jobs: Jobs::default(),
+            script_name: std::env::args().next().unwrap_or_default(),
+            positional_params: Vec::new(),
+            call_stack: Vec::new(),
+            local_scopes: Vec::new(),
+            returning: false,
+            traps: BTreeMap::new(),
+            running_trap: false,
+            conditions: 0
+        };
+        shell.set_pipeline_status(vec![0]);
+        shell
+    }
+
+    /// Whether the commands still to run in the current list should be
+    /// skipped, because of `exit`, `break`, `continue`, `return` or Ctrl-C.
+    pub fn interrupted(&self)-> bool{
+        self.exit_requested || self.loop_control.is_some() || self.returning || signals::interrupt_pending()
+    }
+
+    /// `$-`: the letters of the shell options that are on.
+    pub fn option_flags(&self)-> String{
+        let options = &self.options;
+        let flags = [
+            ('e', options.errexit), ('f', options.noglob), ('i', self.interactive), ('m', self.jobs.enabled()),
+            ('n', options.noexec), ('u', options.nounset), ('x', options.xtrace), ('C', options.noclobber),
+        ];
+        flags.iter().filter(|(_, on)| *on).map(|(letter, _)| letter).collect()
+    }
+
+    /// Records the statuses of a finished pipeline. The pipeline's own status
+    /// is the status of its last stage, or with `pipefail` that of the last
+    /// stage that failed; all of them are kept in `PIPESTATUS`.
+    pub fn set_pipeline_status(&mut self, statuses: Vec<i32>){
+        self.last_status = if self.options.pipefail {
+            statuses.iter().rev().find(|status| **status != 0).copied().unwrap_or(0)
+        }else{
+            statuses.last().copied().unwrap_or(0)
+        };
+        let _ = self.vars.set_array("PIPESTATUS", statuses.iter().map(|s| s.to_string()).collect());
+    }
+}
+
+impl Default for Shell{
+    fn default()-> Self{
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_option_name_and_letter_resolves(){
+        for (name, letter) in ShellOptions::NAMES {
+            let mut options = ShellOptions::default();
+            *options.option_mut(name).unwrap() = true;
+            if let Some(letter) = letter {
+                assert!(*options.letter_mut(letter).unwrap(), "-{} is not {}", letter, name);
+            }
+        }
+        let mut options = ShellOptions::default();
+        assert!(options.option_mut("nosuch").is_none());
+        assert!(options.letter_mut('o').is_none());
+        assert!(options.letter_mut('i').is_none());
+    }
+
+    #[test]
+    fn lists_set_options_in_dollar_dash(){
+        let mut shell = Shell::new();
+        assert_eq!(shell.option_flags(), "");
+        for letter in ['C', 'x', 'u', 'n', 'f', 'e'] {
+            *shell.options.letter_mut(letter).unwrap() = true;
+        }
+        shell.options.pipefail = true;
+        assert_eq!(shell.option_flags(), "efnuxC");
+        shell.interactive = true;
+        shell.options = ShellOptions { errexit: true, ..ShellOptions::default() };
+        assert_eq!(shell.option_flags(), "ei");
+    }
+}
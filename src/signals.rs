@@ -0,0 +1,314 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Signals an interactive shell ignores so that only its foreground job is
+/// killed or stopped by them.
+const IGNORED_SIGNALS: [i32; 5] = [libc::SIGQUIT, libc::SIGTERM, libc::SIGTSTP, libc::SIGTTIN, libc::SIGTTOU];
+
+/// Signals that end a shell at their default action, which it catches while
+/// an `EXIT` trap is set so that the trap still runs.
+const FATAL_SIGNALS: [i32; 3] = [libc::SIGHUP, libc::SIGINT, libc::SIGTERM];
+
+/// The signals `trap -l` lists and `trap` accepts by name, without `SIG`.
+pub const SIGNAL_NAMES: [(&str, i32); 31] = [
+    ("HUP", libc::SIGHUP), ("INT", libc::SIGINT), ("QUIT", libc::SIGQUIT), ("ILL", libc::SIGILL),
+    ("TRAP", libc::SIGTRAP), ("ABRT", libc::SIGABRT), ("BUS", libc::SIGBUS), ("FPE", libc::SIGFPE),
+    ("KILL", libc::SIGKILL), ("USR1", libc::SIGUSR1), ("SEGV", libc::SIGSEGV), ("USR2", libc::SIGUSR2),
+    ("PIPE", libc::SIGPIPE), ("ALRM", libc::SIGALRM), ("TERM", libc::SIGTERM), ("STKFLT", libc::SIGSTKFLT),
+    ("CHLD", libc::SIGCHLD), ("CONT", libc::SIGCONT), ("STOP", libc::SIGSTOP), ("TSTP", libc::SIGTSTP),
+    ("TTIN", libc::SIGTTIN), ("TTOU", libc::SIGTTOU), ("URG", libc::SIGURG), ("XCPU", libc::SIGXCPU),
+    ("XFSZ", libc::SIGXFSZ), ("VTALRM", libc::SIGVTALRM), ("PROF", libc::SIGPROF), ("WINCH", libc::SIGWINCH),
+    ("IO", libc::SIGIO), ("PWR", libc::SIGPWR), ("SYS", libc::SIGSYS),
+];
+
+/// Whether this process still has the interactive shell's dispositions.
+static HANDLING: AtomicBool = AtomicBool::new(false);
+
+/// Set when the shell itself got `SIGINT`, or its foreground job was killed
+/// by it, until the command line is finished.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Signals, one bit each, that have a trap command, that `trap ''` ignores,
+/// and that arrived but whose trap has not run yet.
+static TRAPPED: AtomicU64 = AtomicU64::new(0);
+static IGNORED: AtomicU64 = AtomicU64::new(0);
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+/// Whether the fatal signals are caught for the `EXIT` trap.
+static CATCHING_FATAL: AtomicBool = AtomicBool::new(false);
+
+fn bit(signal: i32)-> u64{
+    1 << (signal as u64 & 63)
+}
+
+fn has(set: &AtomicU64, signal: i32)-> bool{
+    set.load(Ordering::SeqCst) & bit(signal) != 0
+}
+
+extern "C" fn on_interrupt(_: libc::c_int){
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn on_trapped_signal(signal: libc::c_int){
+    PENDING.fetch_or(bit(signal), Ordering::SeqCst);
+}
+
+/// Installs a handler for a signal that lets interrupted system calls
+/// carry on.
+fn catch(signal: i32, handler: extern "C" fn(libc::c_int)){
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESTART;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(signal, &action, std::ptr::null_mut());
+    }
+}
+
+/// Sets up the signal dispositions of an interactive shell: `SIGINT` only
+/// interrupts the command line being run and the job control signals,
+/// `SIGQUIT` and `SIGTERM` are ignored.
+pub fn handle_interactive(){
+    for signal in IGNORED_SIGNALS {
+        unsafe { libc::signal(signal, libc::SIG_IGN) };
+    }
+    catch(libc::SIGINT, on_interrupt);
+    HANDLING.store(true, Ordering::SeqCst);
+}
+
+/// Gives a forked child the default action back for every signal the
+/// interactive shell handles or a trap catches, and for `SIGPIPE`, which
+/// the Rust runtime ignores, so that a stage whose reader has gone away
+/// ends; signals ignored with `trap ''` stay ignored. Safe to call between
+/// `fork` and `exec`.
+pub fn restore_defaults(){
+    let handling = HANDLING.swap(false, Ordering::SeqCst);
+    let caught = TRAPPED.swap(0, Ordering::SeqCst);
+    let fatal = CATCHING_FATAL.swap(false, Ordering::SeqCst);
+    PENDING.store(0, Ordering::SeqCst);
+    for signal in 1..64 {
+        let interactive = handling && (signal == libc::SIGINT || IGNORED_SIGNALS.contains(&signal));
+        let caught = caught & bit(signal) != 0 || fatal && FATAL_SIGNALS.contains(&signal);
+        if (interactive || caught || signal == libc::SIGPIPE) && !has(&IGNORED, signal) {
+            unsafe { libc::signal(signal, libc::SIG_DFL) };
+        }
+    }
+}
+
+/// Makes a background job started without job control immune to the
+/// keyboard's `SIGINT` and `SIGQUIT`, which it would otherwise share with
+/// the shell.
+pub fn ignore_interrupts(){
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+    }
+}
+
+/// Catches a signal for its trap command, which runs once the shell gets to
+/// it between commands.
+pub fn trap(signal: i32){
+    IGNORED.fetch_and(!bit(signal), Ordering::SeqCst);
+    TRAPPED.fetch_or(bit(signal), Ordering::SeqCst);
+    catch(signal, on_trapped_signal);
+}
+
+/// Ignores a signal, as `trap '' SIGNAL` does.
+pub fn ignore(signal: i32){
+    TRAPPED.fetch_and(!bit(signal), Ordering::SeqCst);
+    IGNORED.fetch_or(bit(signal), Ordering::SeqCst);
+    unsafe { libc::signal(signal, libc::SIG_IGN) };
+}
+
+/// Gives a signal back the disposition the shell had for it before any
+/// trap.
+pub fn reset(signal: i32){
+    TRAPPED.fetch_and(!bit(signal), Ordering::SeqCst);
+    IGNORED.fetch_and(!bit(signal), Ordering::SeqCst);
+    PENDING.fetch_and(!bit(signal), Ordering::SeqCst);
+    let handling = HANDLING.load(Ordering::SeqCst);
+    if handling && signal == libc::SIGINT {
+        catch(signal, on_interrupt);
+    }else if handling && IGNORED_SIGNALS.contains(&signal) {
+        unsafe { libc::signal(signal, libc::SIG_IGN) };
+    }else if CATCHING_FATAL.load(Ordering::SeqCst) && FATAL_SIGNALS.contains(&signal) {
+        catch(signal, on_trapped_signal);
+    }else{
+        unsafe { libc::signal(signal, libc::SIG_DFL) };
+    }
+}
+
+/// Starts or stops catching the signals that would otherwise end the shell
+/// without running its `EXIT` trap. Signals with a trap of their own, and
+/// those the interactive shell handles, are left alone.
+pub fn catch_fatal(enable: bool){
+    CATCHING_FATAL.store(enable, Ordering::SeqCst);
+    let handling = HANDLING.load(Ordering::SeqCst);
+    for signal in FATAL_SIGNALS {
+        if has(&TRAPPED, signal) || has(&IGNORED, signal) || handling && signal != libc::SIGHUP {
+            continue;
+        }
+        if enable {
+            catch(signal, on_trapped_signal);
+        }else{
+            unsafe { libc::signal(signal, libc::SIG_DFL) };
+        }
+    }
+}
+
+/// Ends the shell by a signal it caught only for the `EXIT` trap, once the
+/// trap has run, so that its parent sees how it died.
+pub fn die_by(signal: i32)-> !{
+    unsafe {
+        libc::signal(signal, libc::SIG_DFL);
+        libc::kill(libc::getpid(), signal);
+        libc::_exit(128 + signal)
+    }
+}
+
+/// Records an interrupt as if the shell had got `SIGINT`: for the trap on
+/// it if there is one, or else to stop the command line if the shell
+/// handles that signal at all.
+pub fn interrupt(){
+    if has(&TRAPPED, libc::SIGINT) {
+        PENDING.fetch_or(bit(libc::SIGINT), Ordering::SeqCst);
+    }else if HANDLING.load(Ordering::SeqCst) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+}
+
+/// Whether an interrupt is pending, which stops the rest of the command
+/// line.
+pub fn interrupt_pending()-> bool{
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// Clears the pending interrupt.
+///
+/// # Returns
+/// * `bool` - Whether there was one
+pub fn take_interrupt()-> bool{
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
+
+/// Takes the caught signals whose traps have not run yet.
+///
+/// # Returns
+/// * `Vec<i32>` - The signals, lowest first
+pub fn take_pending()-> Vec<i32>{
+    let pending = PENDING.swap(0, Ordering::SeqCst);
+    (1..64).filter(|&signal| pending & bit(signal) != 0).collect()
+}
+
+/// Whether a signal is caught for the `EXIT` trap rather than for a trap
+/// of its own.
+pub fn caught_as_fatal(signal: i32)-> bool{
+    !has(&TRAPPED, signal) && CATCHING_FATAL.load(Ordering::SeqCst) && FATAL_SIGNALS.contains(&signal)
+}
+
+/// The number of a signal given by name, with or without `SIG`, in any
+/// case.
+pub fn signal_number(name: &str)-> Option<i32>{
+    let name = name.to_ascii_uppercase();
+    let name = name.strip_prefix("SIG").unwrap_or(&name);
+    SIGNAL_NAMES.iter().find(|(other, _)| *other == name).map(|(_, number)| *number)
+}
+
+/// The name of a signal with `SIG`, such as `SIGINT`.
+pub fn signal_name(signal: i32)-> String{
+    match SIGNAL_NAMES.iter().find(|(_, number)| *number == signal) {
+        Some((name, _)) => format!("SIG{}", name),
+        None => format!("SIG{}", signal),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `test` in a forked child, so that the dispositions and sets it
+    /// changes are neither seen nor changed by tests in other threads.
+    fn in_child(test: fn())-> bool{
+        match unsafe { libc::fork() } {
+            0 => {
+                let passed = std::panic::catch_unwind(test).is_ok();
+                unsafe { libc::_exit(if passed { 0 } else { 1 }) }
+            },
+            pid => {
+                let mut status = 0;
+                unsafe { libc::waitpid(pid, &mut status, 0) };
+                libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0
+            },
+        }
+    }
+
+    fn disposition(signal: i32)-> libc::sighandler_t{
+        unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            libc::sigaction(signal, std::ptr::null(), &mut action);
+            action.sa_sigaction
+        }
+    }
+
+    #[test]
+    fn takes_pending_signals_exactly_once(){
+        assert!(in_child(|| {
+            trap(libc::SIGWINCH);
+            trap(libc::SIGUSR2);
+            assert!(has(&TRAPPED, libc::SIGUSR2) && has(&TRAPPED, libc::SIGWINCH));
+            assert!(take_pending().is_empty());
+            unsafe {
+                libc::raise(libc::SIGWINCH);
+                libc::raise(libc::SIGUSR2);
+                libc::raise(libc::SIGUSR2);
+            }
+            assert_eq!(take_pending(), [libc::SIGUSR2, libc::SIGWINCH]);
+            assert!(take_pending().is_empty());
+        }));
+    }
+
+    #[test]
+    fn moves_signals_between_trapped_and_ignored(){
+        assert!(in_child(|| {
+            ignore(libc::SIGUSR2);
+            assert!(has(&IGNORED, libc::SIGUSR2) && !has(&TRAPPED, libc::SIGUSR2));
+            assert_eq!(disposition(libc::SIGUSR2), libc::SIG_IGN);
+            trap(libc::SIGUSR2);
+            assert!(has(&TRAPPED, libc::SIGUSR2) && !has(&IGNORED, libc::SIGUSR2));
+            unsafe { libc::raise(libc::SIGUSR2) };
+            reset(libc::SIGUSR2);
+            assert!(!has(&TRAPPED, libc::SIGUSR2) && !has(&IGNORED, libc::SIGUSR2));
+            assert!(take_pending().is_empty());
+            assert_eq!(disposition(libc::SIGUSR2), libc::SIG_DFL);
+        }));
+    }
+
+    #[test]
+    fn restores_default_dispositions_in_children(){
+        assert!(in_child(|| {
+            unsafe { libc::signal(libc::SIGPIPE, libc::SIG_IGN) };
+            trap(libc::SIGUSR1);
+            ignore(libc::SIGUSR2);
+            catch_fatal(true);
+            assert!(caught_as_fatal(libc::SIGTERM) && !caught_as_fatal(libc::SIGUSR1));
+            unsafe { libc::raise(libc::SIGUSR1) };
+            restore_defaults();
+            assert_eq!(TRAPPED.load(Ordering::SeqCst), 0);
+            assert!(take_pending().is_empty());
+            assert!(!caught_as_fatal(libc::SIGTERM));
+            assert_eq!(disposition(libc::SIGUSR1), libc::SIG_DFL);
+            assert_eq!(disposition(libc::SIGTERM), libc::SIG_DFL);
+            assert_eq!(disposition(libc::SIGPIPE), libc::SIG_DFL);
+            assert_eq!(disposition(libc::SIGUSR2), libc::SIG_IGN);
+        }));
+    }
+
+    #[test]
+    fn names_signals(){
+        assert_eq!(signal_number("INT"), Some(libc::SIGINT));
+        assert_eq!(signal_number("sigterm"), Some(libc::SIGTERM));
+        assert_eq!(signal_number("SIG"), None);
+        assert_eq!(signal_number("NOPE"), None);
+        assert_eq!(signal_name(libc::SIGHUP), "SIGHUP");
+        assert_eq!(signal_name(40), "SIG40");
+    }
+}
@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+use std::env;
+
+/// The value of a shell variable.
+#[derive(Debug, Clone)]
+pub enum Value{
+    Scalar(String),
+    /// An indexed array such as `PIPESTATUS`.
+    Array(Vec<String>)
+}
+
+#[derive(Debug, Clone)]
+pub struct Variable{
+    /// `None` for a variable that has attributes, as given by `export NAME`,
+    /// but has never been assigned; it still counts as unset.
+    pub value: Option<Value>,
+    /// Whether the variable is passed to the environment of commands.
+    pub exported: bool,
+    /// Set by `readonly`; the variable can no longer be assigned or unset.
+    pub readonly: bool
+}
+
+/// The shell's variables, starting out as a copy of its environment. The
+/// process environment itself is left alone: commands get their environment
+/// from `environment`.
+pub struct Variables{
+    table: HashMap<String, Variable>
+}
+
+impl Variables{
+    /// Creates the table from the environment the shell was started with;
+    /// every variable in it is exported.
+    pub fn from_env()-> Self{
+        let table = env::vars()
+            .map(|(name, value)| (name, Variable { value: Some(Value::Scalar(value)), exported: true, readonly: false }))
+            .collect();
+        Variables { table }
+    }
+
+    pub fn get(&self, name: &str)-> Option<&Variable>{
+        self.table.get(name)
+    }
+
+    /// Returns the value of a variable as a string; for an array this is its
+    /// first element, as with `$PIPESTATUS`.
+    pub fn get_scalar(&self, name: &str)-> Option<String>{
+        match self.get(name)?.value.as_ref()? {
+            Value::Scalar(value) => Some(value.clone()),
+            Value::Array(values) => values.first().cloned(),
+        }
+    }
+
+    /// Returns an element of an array, counting from the end when `index`
+    /// is negative. A scalar is an array of one element.
+    pub fn get_element(&self, name: &str, index: i64)-> Option<String>{
+        let values = match self.get(name)?.value.as_ref()? {
+            Value::Scalar(value) => std::slice::from_ref(value),
+            Value::Array(values) => values.as_slice(),
+        };
+        let index = if index < 0 { values.len() as i64 + index } else { index };
+        values.get(usize::try_from(index).ok()?).cloned()
+    }
+
+    /// Sets an element of an array, turning a scalar into an array and
+    /// filling any gap before `index` with empty strings.
+    pub fn set_element(&mut self, name: &str, index: i64, value: String)-> Result<(), String>{
+        let variable = self.writable(name)?;
+        let mut values = match variable.value.take() {
+            Some(Value::Scalar(scalar)) => vec![scalar],
+            Some(Value::Array(values)) => values,
+            None => Vec::new(),
+        };
+        let index = if index < 0 { values.len() as i64 + index } else { index };
+        if let Ok(index) = usize::try_from(index) {
+            if index >= values.len() {
+                values.resize(index + 1, String::new());
+            }
+            values[index] = value;
+        }
+        variable.value = Some(Value::Array(values));
+        Ok(())
+    }
+
+    /// Sets a variable, keeping its attributes. Assigning to an array sets
+    /// its first element.
+    ///
+    /// # Returns
+    /// * `Result<(), String>` - An error if the variable is read-only
+    pub fn set(&mut self, name: &str, value: String)-> Result<(), String>{
+        let variable = self.writable(name)?;
+        match &mut variable.value {
+            Some(Value::Array(values)) if !values.is_empty() => values[0] = value,
+            _ => variable.value = Some(Value::Scalar(value)),
+        }
+        Ok(())
+    }
+
+    /// Replaces a variable with an array.
+    pub fn set_array(&mut self, name: &str, values: Vec<String>)-> Result<(), String>{
+        self.writable(name)?.value = Some(Value::Array(values));
+        Ok(())
+    }
+
+    /// Removes a variable and its attributes.
+    pub fn unset(&mut self, name: &str)-> Result<(), String>{
+        if self.get(name).is_some_and(|variable| variable.readonly) {
+            return Err(format!("{}: cannot unset: readonly variable", name));
+        }
+        self.table.remove(name);
+        Ok(())
+    }
+
+    /// Marks a variable as exported or not, declaring it if needed.
+    pub fn set_exported(&mut self, name: &str, exported: bool){
+        self.declare(name).exported = exported;
+    }
+
+    /// Marks a variable as read-only, declaring it if needed.
+    pub fn set_readonly(&mut self, name: &str){
+        self.declare(name).readonly = true;
+    }
+
+    /// Takes a copy of a variable, to put it back with `restore` after a
+    /// temporary assignment.
+    pub fn save(&self, name: &str)-> Option<Variable>{
+        self.get(name).cloned()
+    }
+
+    pub fn restore(&mut self, name: &str, saved: Option<Variable>){
+        match saved {
+            Some(variable) => { self.table.insert(name.to_string(), variable); },
+            None => { self.table.remove(name); },
+        }
+    }
+
+    /// The name and value of every exported scalar variable, as passed to the
+    /// environment of commands.
+    pub fn environment(&self)-> Vec<(String, String)>{
+        self.table.iter()
+            .filter(|(_, variable)| variable.exported)
+            .filter_map(|(name, variable)| match &variable.value {
+                Some(Value::Scalar(value)) => Some((name.clone(), value.clone())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Every variable, sorted by name.
+    pub fn sorted(&self)-> Vec<(&String, &Variable)>{
+        let mut variables: Vec<_> = self.table.iter().collect();
+        variables.sort_by(|a, b| a.0.cmp(b.0));
+        variables
+    }
+
+    fn declare(&mut self, name: &str)-> &mut Variable{
+        self.table.entry(name.to_string())
+            .or_insert(Variable { value: None, exported: false, readonly: false })
+    }
+
+    fn writable(&mut self, name: &str)-> Result<&mut Variable, String>{
+        let variable = self.declare(name);
+        if variable.readonly {
+            return Err(format!("{}: readonly variable", name));
+        }
+        Ok(variable)
+    }
+}
+
+/// Formats a variable the way `export -p` and `readonly -p` list it, as a
+/// `declare` command that recreates it.
+pub fn declaration(name: &str, variable: &Variable)-> String{
+    let mut flags = String::new();
+    if matches!(variable.value, Some(Value::Array(_))) {
+        flags.push('a');
+    }
+    if variable.readonly {
+        flags.push('r');
+    }
+    if variable.exported {
+        flags.push('x');
+    }
+    if flags.is_empty() {
+        flags.push('-');
+    }
+    match &variable.value {
+        None => format!("declare -{} {}", flags, name),
+        Some(Value::Scalar(value)) => format!("declare -{} {}=\"{}\"", flags, name, escape(value)),
+        Some(Value::Array(values)) => {
+            let elements: Vec<String> = values.iter().enumerate()
+                .map(|(i, value)| format!("[{}]=\"{}\"", i, escape(value)))
+                .collect();
+            format!("declare -{} {}=({})", flags, name, elements.join(" "))
+        },
+    }
+}
+
+/// Escapes the characters that are special inside double quotes.
+fn escape(value: &str)-> String{
+    let mut escaped = String::new();
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::run_program;
+    use crate::parser::parse_program;
+    use crate::shell::Shell;
+
+    fn run(line: &str, shell: &mut Shell){
+        run_program(&parse_program(line).unwrap(), shell);
+    }
+
+    fn is_exported(vars: &Variables, name: &str)-> bool{
+        vars.environment().iter().any(|(exported, _)| exported == name)
+    }
+
+    #[test]
+    fn keeps_attributes_across_assignments(){
+        let mut vars = Variables { table: HashMap::new() };
+        vars.set_exported("x", true);
+        assert_eq!(vars.get_scalar("x"), None);
+        assert!(!is_exported(&vars, "x"));
+        vars.set("x", "1".to_string()).unwrap();
+        assert_eq!(vars.environment(), [("x".to_string(), "1".to_string())]);
+        vars.set_array("a", vec!["b".to_string(), "c".to_string()]).unwrap();
+        vars.set("a", "z".to_string()).unwrap();
+        assert_eq!(vars.get_scalar("a").as_deref(), Some("z"));
+        assert_eq!(vars.get_element("a", -1).as_deref(), Some("c"));
+    }
+
+    #[test]
+    fn export_n_keeps_the_value(){
+        let shell = &mut Shell::new();
+        run("export vars_test=1", shell);
+        assert!(is_exported(&shell.vars, "vars_test"));
+        run("export -n vars_test", shell);
+        assert!(!is_exported(&shell.vars, "vars_test"));
+        assert_eq!(shell.vars.get_scalar("vars_test").as_deref(), Some("1"));
+        assert_eq!(shell.last_status, 0);
+    }
+
+    #[test]
+    fn refuses_to_change_readonly_variables(){
+        let mut vars = Variables { table: HashMap::new() };
+        vars.set("r", "1".to_string()).unwrap();
+        vars.set_readonly("r");
+        assert_eq!(vars.set("r", "2".to_string()), Err("r: readonly variable".to_string()));
+        assert_eq!(vars.set_element("r", 1, "2".to_string()), Err("r: readonly variable".to_string()));
+        assert_eq!(vars.unset("r"), Err("r: cannot unset: readonly variable".to_string()));
+        assert_eq!(vars.get_scalar("r").as_deref(), Some("1"));
+
+        let shell = &mut Shell::new();
+        run("readonly r=1; unset r; s=after", shell);
+        assert_eq!(shell.vars.get_scalar("r").as_deref(), Some("1"));
+        assert_eq!(shell.vars.get_scalar("s").as_deref(), Some("after"));
+    }
+
+    #[test]
+    fn readonly_assignment_ends_a_non_interactive_shell(){
+        let shell = &mut Shell::new();
+        shell.interactive = true;
+        run("readonly r=1; r=2; s=after", shell);
+        assert_eq!(shell.vars.get_scalar("s").as_deref(), Some("after"));
+        assert!(!shell.exit_requested);
+
+        let shell = &mut Shell::new();
+        run("readonly r=1; r=2; s=after", shell);
+        assert_eq!(shell.vars.get_scalar("s"), None);
+        assert!(shell.exit_requested);
+        assert_eq!(shell.last_status, 1);
+    }
+
+    #[test]
+    fn prefix_assignments_last_for_one_command(){
+        let shell = &mut Shell::new();
+        run("p=old; f() { seen=$p$u; }; p=new u=1 f", shell);
+        assert_eq!(shell.vars.get_scalar("seen").as_deref(), Some("new1"));
+        assert_eq!(shell.vars.get_scalar("p").as_deref(), Some("old"));
+        assert!(!is_exported(&shell.vars, "p"));
+        assert_eq!(shell.vars.get_scalar("u"), None);
+
+        run("readonly r=1; r=2 f", shell);
+        assert_eq!(shell.last_status, 1);
+        assert!(!shell.exit_requested);
+    }
+}